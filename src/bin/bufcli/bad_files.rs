@@ -0,0 +1,40 @@
+use crate::CmdLineArgs;
+use bufcli::{ClimoDB, ClimoQueryInterface, FileStatus};
+use bufkit_data::Archive;
+use std::error::Error;
+
+pub(crate) fn bad_files_run(args: CmdLineArgs) -> Result<(), Box<dyn Error>> {
+    let arch = Archive::connect(&args.root)?;
+    let climo_db = ClimoDB::connect_or_create(&args.root)?;
+    let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+    for (site, model) in args.site_model_pairs.iter() {
+        let mut bad_files = climo_db.bad_files(site, *model)?;
+
+        // Files that were purged are no longer a problem, and skipped files were mostly used.
+        bad_files.retain(|(init_time, status, _)| {
+            (args.include_skipped || *status != FileStatus::Skipped)
+                && arch
+                    .file_exists(site.station_num, *model, *init_time)
+                    .unwrap_or(true)
+        });
+
+        if bad_files.is_empty() {
+            continue;
+        }
+
+        println!();
+        println!("{} - {}", site.description(), model);
+        println!("{:>16}{:>14}  Reason", "Init Time", "Status");
+        for (init_time, status, reason) in bad_files.iter() {
+            println!(
+                "{:>16}{:>14}  {}",
+                init_time.format("%Y-%m-%d %HZ"),
+                status,
+                reason.as_deref().unwrap_or("-")
+            );
+        }
+    }
+
+    Ok(())
+}
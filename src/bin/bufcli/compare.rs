@@ -0,0 +1,252 @@
+use crate::{CmdLineArgs, MONTHS, PERCENTILES};
+use bufcli::{
+    correlation, direction_difference, mean_direction, BufcliError, ClimoDB, ClimoElement,
+    ClimoQueryFilter, ClimoQueryInterface, ErrorSummary, ModelPairs, Summary,
+};
+use bufkit_data::{Model, SiteInfo};
+use chrono::{Datelike, FixedOffset, TimeZone, Timelike};
+use std::error::Error;
+
+pub(crate) fn compare_run(args: CmdLineArgs) -> Result<(), Box<dyn Error>> {
+    let elements = args.selected_elements();
+    if elements.is_empty() {
+        return Err(BufcliError::new("No elements selected to compare, use --elements").into());
+    }
+
+    let climo_db = ClimoDB::connect_or_create(&args.root)?;
+    let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+    // Group the models by site, keeping the order they were given in.
+    let mut sites: Vec<(&SiteInfo, Vec<Model>)> = vec![];
+    for (site, model) in args.site_model_pairs.iter() {
+        match sites
+            .iter_mut()
+            .find(|(s, _)| s.station_num == site.station_num)
+        {
+            Some((_, models)) => models.push(*model),
+            None => sites.push((site, vec![*model])),
+        }
+    }
+
+    let filters: Vec<(ClimoQueryFilter, String)> = if args.lead_times.is_empty() {
+        vec![(args.filter.clone(), args.period.clone())]
+    } else {
+        args.lead_times
+            .iter()
+            .map(|&lead_time| {
+                (
+                    args.filter.clone().with_lead_time(lead_time),
+                    format!("{} - {} hour forecasts", args.period, lead_time),
+                )
+            })
+            .collect()
+    };
+
+    for (site, models) in sites.iter() {
+        if models.len() < 2 {
+            println!();
+            println!(
+                "{} - only one model, nothing to compare",
+                site.description()
+            );
+            continue;
+        }
+
+        for (i, &model_a) in models.iter().enumerate() {
+            for &model_b in models[(i + 1)..].iter() {
+                for &element in elements.iter() {
+                    for (filter, period) in filters.iter() {
+                        let pairs =
+                            climo_db.model_pairs(site, model_a, model_b, element, filter)?;
+
+                        println!();
+                        println!(
+                            "{} - {} - {} vs {} - {}",
+                            site.description(),
+                            element,
+                            model_a,
+                            model_b,
+                            period
+                        );
+
+                        let groups = group_pairs(site, &pairs);
+                        print_differences(element, &groups);
+                        print_distributions(model_a, model_b, element, &groups);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Split the pairs of values up by the local month and by the local hour of their valid time,
+/// followed by all of them together, each with a label.
+fn group_pairs(site: &SiteInfo, pairs: &ModelPairs) -> Vec<(String, Vec<(f64, f64)>)> {
+    let tz = site
+        .time_zone
+        .unwrap_or_else(|| FixedOffset::west_opt(0).unwrap());
+
+    let mut by_month: Vec<Vec<(f64, f64)>> = vec![vec![]; 12];
+    let mut by_hour: Vec<Vec<(f64, f64)>> = vec![vec![]; 24];
+    for &(valid_time, val_a, val_b) in pairs.iter() {
+        let lcl_time = tz.from_utc_datetime(&valid_time);
+        by_month[lcl_time.month0() as usize].push((val_a, val_b));
+        by_hour[lcl_time.hour() as usize].push((val_a, val_b));
+    }
+
+    let months = by_month
+        .into_iter()
+        .enumerate()
+        .map(|(month, pairs)| (MONTHS[month].to_owned(), pairs));
+    let hours = by_hour
+        .into_iter()
+        .enumerate()
+        .map(|(hour, pairs)| (format!("{:02}L", hour), pairs));
+    let all = pairs.iter().map(|&(_, a, b)| (a, b)).collect();
+
+    months
+        .chain(hours)
+        .chain(std::iter::once(("all".to_owned(), all)))
+        .collect()
+}
+
+/// Print the differences between the models by local month and by local hour of the valid time.
+fn print_differences(element: ClimoElement, groups: &[(String, Vec<(f64, f64)>)]) {
+    println!(
+        "{:<8}{:>8}{:>10}{:>10}{:>10}{:>8}",
+        "", "count", "mean diff", "mae", "rmse", "corr"
+    );
+
+    for (label, pairs) in groups.iter() {
+        if pairs.is_empty() && label == "all" {
+            println!("{:<8}{:>8}", label, 0);
+        } else {
+            print_difference_row(label, element, pairs);
+        }
+    }
+}
+
+fn print_difference_row(label: &str, element: ClimoElement, pairs: &[(f64, f64)]) {
+    let diffs = if element.is_direction() {
+        ErrorSummary::new_directions(pairs)
+    } else {
+        ErrorSummary::new(pairs)
+    };
+    let diffs = match diffs {
+        Some(diffs) => diffs,
+        None => return,
+    };
+
+    print!(
+        "{:<8}{:>8}{:>10.2}{:>10.2}{:>10.2}",
+        label,
+        diffs.count(),
+        diffs.bias(),
+        diffs.mae(),
+        diffs.rmse()
+    );
+    match correlation(pairs).filter(|_| !element.is_direction()) {
+        Some(corr) => println!("{:>8.2}", corr),
+        None => println!("{:>8}", "-"),
+    }
+}
+
+/// Print the percentiles of each model, then the shift between them by local month and by local
+/// hour of the valid time. Directions only have a mean.
+fn print_distributions(
+    model_a: Model,
+    model_b: Model,
+    element: ClimoElement,
+    groups: &[(String, Vec<(f64, f64)>)],
+) {
+    let percentiles: &[f64] = if element.is_direction() {
+        &[]
+    } else {
+        &PERCENTILES
+    };
+
+    let print_header = |label: &str| {
+        print!("{:<8}{:>10}", label, "mean");
+        for pct in percentiles.iter() {
+            print!("{:>10}", format!("p{}", pct));
+        }
+        println!();
+    };
+
+    let all = match groups.last() {
+        Some((_, all)) => all,
+        None => return,
+    };
+    let (dist_a, dist_b) = match (
+        Distribution::new(element, all.iter().map(|&(a, _)| a).collect()),
+        Distribution::new(element, all.iter().map(|&(_, b)| b).collect()),
+    ) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return,
+    };
+
+    println!();
+    print_header("");
+    for (model, dist) in [(model_a, &dist_a), (model_b, &dist_b)].iter() {
+        print!("{:<8}{:>10.1}", model.as_static_str(), dist.mean);
+        for &pct in percentiles.iter() {
+            print!("{:>10.1}", dist.percentile(pct));
+        }
+        println!();
+    }
+
+    println!();
+    println!("shift of {} from {}", model_a, model_b);
+    print_header("");
+    for (label, pairs) in groups.iter() {
+        let (dist_a, dist_b) = match (
+            Distribution::new(element, pairs.iter().map(|&(a, _)| a).collect()),
+            Distribution::new(element, pairs.iter().map(|&(_, b)| b).collect()),
+        ) {
+            (Some(a), Some(b)) => (a, b),
+            _ => continue,
+        };
+
+        let shift = if element.is_direction() {
+            direction_difference(dist_a.mean, dist_b.mean)
+        } else {
+            dist_a.mean - dist_b.mean
+        };
+        print!("{:<8}{:>10.1}", label, shift);
+        for &pct in percentiles.iter() {
+            print!("{:>10.1}", dist_a.percentile(pct) - dist_b.percentile(pct));
+        }
+        println!();
+    }
+}
+
+/// The mean and, for elements that aren't directions, the percentiles of one model's values.
+struct Distribution {
+    mean: f64,
+    summary: Option<Summary>,
+}
+
+impl Distribution {
+    fn new(element: ClimoElement, vals: Vec<f64>) -> Option<Self> {
+        if element.is_direction() {
+            mean_direction(&vals).map(|mean| Distribution {
+                mean,
+                summary: None,
+            })
+        } else {
+            Summary::new(vals).map(|summary| Distribution {
+                mean: summary.mean(),
+                summary: Some(summary),
+            })
+        }
+    }
+
+    fn percentile(&self, pct: f64) -> f64 {
+        self.summary
+            .as_ref()
+            .map(|summary| summary.percentile(pct))
+            .unwrap_or(f64::NAN)
+    }
+}
@@ -2,12 +2,27 @@
 //!
 //! Generate ad hoc model climatologies from Bufkit soundings and store the intermediate data in the
 //! archive. These can be queried later by other tools to provide context to any given analysis.
+mod bad_files;
 mod builder;
+mod compare;
+mod query;
+mod rank;
+mod verify;
 
+use bufcli::{BufcliError, ClimoElement, ClimoElementRegistry, ClimoQueryFilter};
 use bufkit_data::{Archive, BufkitDataErr, Model, SiteInfo};
+use chrono::{NaiveDate, NaiveDateTime};
 use std::{error::Error, path::PathBuf, str::FromStr};
 use strum::IntoEnumIterator;
 
+/// The percentiles shown in tables of statistics.
+pub(crate) const PERCENTILES: [f64; 7] = [10.0, 25.0, 50.0, 75.0, 90.0, 95.0, 99.0];
+
+/// Short names of the months for labeling the rows of tables.
+pub(crate) const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
 fn main() {
     if let Err(e) = run() {
         println!("error: {}", e);
@@ -34,7 +49,13 @@ fn run() -> Result<(), Box<dyn Error>> {
     match args.operation.as_ref() {
         "build" => builder::build_climo(args),
         "update" => builder::build_climo(args),
+        "recompute" => builder::build_climo(args),
         "reset" => reset(args),
+        "query" => query::query_climo(args),
+        "rank" => rank::rank_run(args),
+        "verify" => verify::verify_run(args),
+        "compare" => compare::compare_run(args),
+        "bad-files" => bad_files::bad_files_run(args),
         _ => bail("Unknown operation."),
     }
 }
@@ -44,6 +65,27 @@ pub(crate) struct CmdLineArgs {
     root: PathBuf,
     site_model_pairs: Vec<(SiteInfo, Model)>,
     operation: String,
+    elements: Vec<ClimoElement>,
+    registry: ClimoElementRegistry,
+    pft_elements: Vec<ClimoElement>,
+    lead_times: Vec<u32>,
+    filter: ClimoQueryFilter,
+    period: String,
+    window: u32,
+    init_time: Option<NaiveDateTime>,
+    purge_bad_files: bool,
+    include_skipped: bool,
+}
+
+impl CmdLineArgs {
+    /// The elements selected with --elements followed by the PFT variants from --pft-wind.
+    fn selected_elements(&self) -> Vec<ClimoElement> {
+        self.elements
+            .iter()
+            .chain(self.pft_elements.iter())
+            .copied()
+            .collect()
+    }
 }
 
 fn parse_args() -> Result<CmdLineArgs, Box<dyn Error>> {
@@ -76,6 +118,136 @@ fn parse_args() -> Result<CmdLineArgs, Box<dyn Error>> {
                     " Default is to use all possible values."
                 )),
         )
+        .arg(
+            clap::Arg::new("elements")
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .short('e')
+                .long("elements")
+                .takes_value(true)
+                .possible_values(
+                    ClimoElement::ALL
+                        .iter()
+                        .map(|val| val.as_static_str())
+                        .chain(std::iter::once("all"))
+                        .collect::<Vec<&str>>(),
+                )
+                .help("Elements for this operation, 'all' selects every one."),
+        )
+        .arg(
+            clap::Arg::new("pft-wind")
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .long("pft-wind")
+                .takes_value(true)
+                .help("Add PFT variants with a fixed mean wind speed (m/s).")
+                .long_help(concat!(
+                    "Add PFT variants that assume a fixed mean wind speed (m/s) in the mixed layer",
+                    " instead of using the wind in the sounding, e.g. --pft-wind 5,10,20. They are",
+                    " named pft_<speed>mps and are used along with any --elements."
+                )),
+        )
+        .arg(
+            clap::Arg::new("pft-moisture-ratio")
+                .long("pft-moisture-ratio")
+                .takes_value(true)
+                .help("Heating (K) needed to add 1 g/kg of moisture to a plume for the PFT.")
+                .long_help(concat!(
+                    "Heating (K) needed to add 1 g/kg of moisture to a plume, used for the PFT and",
+                    " all the --pft-wind variants. The default is the ratio already used in the",
+                    " database, or 15 for a new one. Values already in the database are not",
+                    " changed, recompute them to use a new ratio."
+                )),
+        )
+        .arg(
+            clap::Arg::new("lead-times")
+                .multiple_values(true)
+                .use_value_delimiter(true)
+                .long("lead-times")
+                .takes_value(true)
+                .help("Forecast lead times (hours) to store or query.")
+                .long_help(concat!(
+                    "Forecast lead times (hours) to store or query, e.g. --lead-times 24,48,72.",
+                    " When building, updating, or recomputing, the selected elements are also",
+                    " stored for these lead times of every model run. A query then reports the",
+                    " statistics of the forecasts at each of these lead times instead of the",
+                    " analyses, and verify compares them with the analyses."
+                )),
+        )
+        .arg(
+            clap::Arg::new("month")
+                .long("month")
+                .takes_value(true)
+                .conflicts_with_all(&["season", "day"])
+                .help("Limit a query to a month (1-12)."),
+        )
+        .arg(
+            clap::Arg::new("season")
+                .long("season")
+                .takes_value(true)
+                .possible_values(["djf", "mam", "jja", "son"])
+                .conflicts_with("day")
+                .help("Limit a query to a season."),
+        )
+        .arg(
+            clap::Arg::new("day")
+                .long("day")
+                .takes_value(true)
+                .help("Limit a query to a window around a day of the year (MM-DD).")
+                .long_help(concat!(
+                    "Limit a query to a window around a day of the year (MM-DD). The width of the",
+                    " window is set with --window."
+                )),
+        )
+        .arg(
+            clap::Arg::new("window")
+                .long("window")
+                .takes_value(true)
+                .default_value("15")
+                .help("Number of days either side of --day to include.")
+                .long_help(concat!(
+                    "Number of days either side of --day to include. When ranking a model run",
+                    " this is the number of days either side of each valid time to include in",
+                    " the climatology."
+                )),
+        )
+        .arg(
+            clap::Arg::new("hour")
+                .long("hour")
+                .takes_value(true)
+                .help("Limit a query to a local hour (0-23)."),
+        )
+        .arg(
+            clap::Arg::new("init-time")
+                .long("init-time")
+                .takes_value(true)
+                .help("Model initialization time to rank (YYYYMMDDHH).")
+                .long_help(concat!(
+                    "Model initialization time to rank (YYYYMMDDHH). Default is to use the most",
+                    " recent run in the archive."
+                )),
+        )
+        .arg(
+            clap::Arg::new("purge-bad-files")
+                .long("purge-bad-files")
+                .help("Remove files that can't be parsed from the archive.")
+                .long_help(concat!(
+                    "Remove files that can't be parsed from the archive when building or",
+                    " updating. By default they are left in the archive and recorded in the",
+                    " climate database, see the bad-files operation. Files that were already",
+                    " recorded as bad are processed again so they can be removed."
+                )),
+        )
+        .arg(
+            clap::Arg::new("include-skipped")
+                .long("include-skipped")
+                .help("Also list files that were only partly used with bad-files.")
+                .long_help(concat!(
+                    "Also list the files that were parsed but had some data that could not be",
+                    " used with the bad-files operation, e.g. soundings without a valid time or",
+                    " a location."
+                )),
+        )
         .arg(
             clap::Arg::new("root")
                 .short('r')
@@ -85,7 +257,6 @@ fn parse_args() -> Result<CmdLineArgs, Box<dyn Error>> {
                 .long_help(
                     "Set the root directory of the archive you are invoking this command for.",
                 )
-                .conflicts_with("create")
                 .global(true),
         )
         .arg(
@@ -93,12 +264,36 @@ fn parse_args() -> Result<CmdLineArgs, Box<dyn Error>> {
                 .index(1)
                 .takes_value(true)
                 .required(true)
-                .possible_values(&["build", "reset", "update"])
-                .help("Build, update, or delete the climatology database.")
+                .possible_values([
+                    "build",
+                    "reset",
+                    "update",
+                    "recompute",
+                    "query",
+                    "rank",
+                    "verify",
+                    "compare",
+                    "bad-files",
+                ])
+                .help("Build, update, delete, query, verify, or compare the climatology database.")
                 .long_help(concat!(
-                    "Either build, update, or reset the climate database. 'reset' deletes the",
-                    " whole climate database and starts over fresh. Update will only add data for",
-                    " dates not already in the database.",
+                    "Either build, update, reset, recompute, query, rank against, verify, or",
+                    " compare models in the climate database. 'reset' deletes the whole climate",
+                    " database and starts over fresh. Update only processes files that are new or",
+                    " have changed since they were processed. Build and update calculate all the",
+                    " elements unless some are selected with --elements. Recompute recalculates",
+                    " only the elements selected with --elements for dates already in the",
+                    " database. Query prints summary statistics for the selected elements over a",
+                    " month, season, or day of year window. Rank prints the percentile of each",
+                    " forecast hour of a model run against the climatology, for hdw, blow_up_dt,",
+                    " pft, dcape, vent_index, and pw unless others are selected with --elements.",
+                    " Verify compares the forecasts stored for --lead-times with the analyses",
+                    " valid at the same time and prints their errors by lead time and month.",
+                    " Compare pairs the values of each model at a site with the other models at",
+                    " the same valid time and prints their differences and distributions by local",
+                    " month and hour. Bad-files lists",
+                    " the files in the archive that could not be parsed and why, and those that",
+                    " were only partly used with --include-skipped.",
                 )),
         );
 
@@ -183,13 +378,126 @@ fn parse_args() -> Result<CmdLineArgs, Box<dyn Error>> {
 
     let operation: String = matches.value_of("operation").map(str::to_owned).unwrap();
 
+    let elements: Vec<ClimoElement> = match matches.values_of("elements") {
+        Some(elem_iter) if elem_iter.clone().any(|elem| elem == "all") => {
+            ClimoElement::ALL.to_vec()
+        }
+        elem_iter => elem_iter
+            .into_iter()
+            .flatten()
+            .map(ClimoElement::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+
+    let mut registry = ClimoElementRegistry::new();
+    if let Some(moisture_ratio) = matches.value_of("pft-moisture-ratio") {
+        registry.set_pft_moisture_ratio(moisture_ratio.parse()?);
+    }
+
+    let pft_elements: Vec<ClimoElement> = matches
+        .values_of("pft-wind")
+        .into_iter()
+        .flatten()
+        .map(|wind| Ok(registry.add_pft(wind.parse()?)?))
+        .collect::<Result<_, Box<dyn Error>>>()?;
+
+    let lead_times: Vec<u32> = matches
+        .values_of("lead-times")
+        .into_iter()
+        .flatten()
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+
+    let (filter, period) = parse_period(&matches)?;
+
+    let window: u32 = matches.value_of("window").unwrap().parse()?;
+
+    let init_time: Option<NaiveDateTime> = matches
+        .value_of("init-time")
+        .map(|init_time| NaiveDateTime::parse_from_str(&format!("{}00", init_time), "%Y%m%d%H%M"))
+        .transpose()?;
+
     Ok(CmdLineArgs {
         root,
         site_model_pairs,
         operation,
+        elements,
+        registry,
+        pft_elements,
+        lead_times,
+        filter,
+        period,
+        window,
+        init_time,
+        purge_bad_files: matches.is_present("purge-bad-files"),
+        include_skipped: matches.is_present("include-skipped"),
     })
 }
 
+/// Build a query filter and a description of it from the month, season, day, and hour options.
+fn parse_period(matches: &clap::ArgMatches) -> Result<(ClimoQueryFilter, String), Box<dyn Error>> {
+    const MONTH_NAMES: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+
+    let mut filter = ClimoQueryFilter::new();
+    let mut period: String;
+
+    if let Some(month) = matches.value_of("month") {
+        let month: u32 = month.parse()?;
+        if !(1..=12).contains(&month) {
+            return Err(BufcliError::new("Month must be between 1 and 12").into());
+        }
+
+        filter = filter.with_months(&[month]);
+        period = MONTH_NAMES[month as usize - 1].to_owned();
+    } else if let Some(season) = matches.value_of("season") {
+        let months = match season {
+            "djf" => [12, 1, 2],
+            "mam" => [3, 4, 5],
+            "jja" => [6, 7, 8],
+            "son" => [9, 10, 11],
+            _ => unreachable!(),
+        };
+
+        filter = filter.with_months(&months);
+        period = season.to_uppercase();
+    } else if let Some(day) = matches.value_of("day") {
+        let window: u32 = matches.value_of("window").unwrap().parse()?;
+
+        // Use a leap year so February 29th is allowed.
+        let center = NaiveDate::parse_from_str(&format!("2000-{}", day), "%Y-%m-%d")?;
+
+        filter = filter.with_day_of_year_window(center, window);
+        period = format!("{} +/- {} days", center.format("%b %d"), window);
+    } else {
+        period = "All dates".to_owned();
+    }
+
+    if let Some(hour) = matches.value_of("hour") {
+        let hour: u32 = hour.parse()?;
+        if hour > 23 {
+            return Err(BufcliError::new("Hour must be between 0 and 23").into());
+        }
+
+        filter = filter.with_local_hour_range(hour, hour);
+        period += &format!(" at {:02} LST", hour);
+    }
+
+    Ok((filter, period))
+}
+
 fn reset(args: CmdLineArgs) -> Result<(), Box<dyn Error>> {
     bufcli::ClimoDB::delete_climo_db(&args.root)
 }
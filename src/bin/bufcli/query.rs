@@ -0,0 +1,121 @@
+use crate::{CmdLineArgs, PERCENTILES};
+use bufcli::{
+    mean_direction, BufcliError, ClimoDB, ClimoElement, ClimoQueryFilter, ClimoQueryInterface,
+    Summary,
+};
+use bufkit_data::{Model, SiteInfo};
+use std::error::Error;
+
+pub(crate) fn query_climo(args: CmdLineArgs) -> Result<(), Box<dyn Error>> {
+    let elements = args.selected_elements();
+    if elements.is_empty() {
+        return Err(BufcliError::new("No elements selected for query, use --elements").into());
+    }
+
+    let climo_db = ClimoDB::connect_or_create(&args.root)?;
+    let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+    for (site, model) in args.site_model_pairs.iter() {
+        if args.lead_times.is_empty() {
+            print_table(
+                &climo_db,
+                site,
+                *model,
+                &elements,
+                &args.filter,
+                &args.period,
+            )?;
+        }
+
+        for &lead_time in args.lead_times.iter() {
+            print_table(
+                &climo_db,
+                site,
+                *model,
+                &elements,
+                &args.filter.clone().with_lead_time(lead_time),
+                &format!("{} - {} hour forecasts", args.period, lead_time),
+            )?;
+        }
+    }
+
+    // Show how elements with parameters were calculated.
+    println!();
+    for &element in elements.iter() {
+        let params = climo_db.element_params(element)?;
+        if params.is_empty() {
+            continue;
+        }
+
+        let params: Vec<String> = params
+            .iter()
+            .map(|(param, value)| format!("{} = {}", param, value))
+            .collect();
+        println!("{}: {}", element, params.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Print the summary statistics of `elements` for a site and model.
+fn print_table(
+    climo_db: &ClimoQueryInterface,
+    site: &SiteInfo,
+    model: Model,
+    elements: &[ClimoElement],
+    filter: &ClimoQueryFilter,
+    title: &str,
+) -> Result<(), Box<dyn Error>> {
+    println!();
+    println!("{} - {} - {}", site.description(), model, title);
+
+    print!(
+        "{:<20}{:>8}{:>10}{:>10}{:>10}",
+        "element", "count", "min", "max", "mean"
+    );
+    for pct in PERCENTILES.iter() {
+        print!("{:>10}", format!("p{}", pct));
+    }
+    println!();
+
+    for &element in elements.iter() {
+        let vals = climo_db.values(site, model, element, filter)?;
+
+        // Only the mean of directions makes sense, and it has to go around the circle.
+        if element.is_direction() {
+            print!("{:<20}{:>8}{:>10}{:>10}", element, vals.len(), "-", "-");
+            match mean_direction(&vals) {
+                Some(mean) => print!("{:>10.1}", mean),
+                None => print!("{:>10}", "-"),
+            }
+            for _ in PERCENTILES.iter() {
+                print!("{:>10}", "-");
+            }
+            println!();
+            continue;
+        }
+
+        let summary = match Summary::new(vals) {
+            Some(summary) => summary,
+            None => {
+                println!("{:<20}{:>8}", element, 0);
+                continue;
+            }
+        };
+
+        print!(
+            "{:<20}{:>8}{:>10.1}{:>10.1}{:>10.1}",
+            element,
+            summary.count(),
+            summary.min(),
+            summary.max(),
+            summary.mean()
+        );
+        for &pct in PERCENTILES.iter() {
+            print!("{:>10.1}", summary.percentile(pct));
+        }
+        println!();
+    }
+
+    Ok(())
+}
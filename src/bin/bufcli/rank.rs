@@ -0,0 +1,176 @@
+use crate::CmdLineArgs;
+use bufcli::{ClimoDB, ClimoElement, ClimoQueryFilter, ClimoQueryInterface, StatsRecord};
+use bufkit_data::{Archive, Model, SiteInfo};
+use chrono::{FixedOffset, NaiveDate, TimeZone, Timelike};
+use sounding_bufkit::BufkitData;
+use std::{collections::HashMap, error::Error};
+
+/// The elements ranked when none are selected, all of them would make the lines too long to read.
+const DEFAULT_ELEMENTS: [ClimoElement; 6] = [
+    ClimoElement::HDW,
+    ClimoElement::BlowUpDt,
+    ClimoElement::PFT,
+    ClimoElement::DCAPE,
+    ClimoElement::VentilationIndex,
+    ClimoElement::PW,
+];
+
+pub(crate) fn rank_run(args: CmdLineArgs) -> Result<(), Box<dyn Error>> {
+    let selected: Vec<ClimoElement> = if args.elements.is_empty() {
+        DEFAULT_ELEMENTS
+            .iter()
+            .chain(args.pft_elements.iter())
+            .copied()
+            .collect()
+    } else {
+        args.selected_elements()
+    };
+
+    let mut registry = args.registry.clone();
+    registry.select(&selected)?;
+    let elements = registry.elements();
+
+    let arch = Archive::connect(&args.root)?;
+    let climo_db = ClimoDB::connect_or_create(&args.root)?;
+    let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+    for (site, model) in args.site_model_pairs.iter() {
+        let init_time = match args.init_time {
+            Some(init_time) => init_time,
+            None => match arch.inventory(site.station_num, *model)?.last() {
+                Some(&init_time) => init_time,
+                None => continue,
+            },
+        };
+
+        let data = arch.retrieve(site.station_num, *model, init_time)?;
+        let bufkit_data = BufkitData::init(&data, "")?;
+
+        let tz = site
+            .time_zone
+            .unwrap_or_else(|| FixedOffset::west_opt(0).unwrap());
+        let mut climo = ClimoWindows::new(&climo_db, site, *model, args.window);
+
+        println!();
+        println!(
+            "{} - {} - {} +/- {} days",
+            site.description(),
+            model,
+            init_time.format("%Y-%m-%d %HZ"),
+            args.window
+        );
+
+        print!("{:>5}{:>16}", "lead", "valid time");
+        for element in elements.iter() {
+            print!("{:>22}", element);
+        }
+        println!();
+
+        for (snd, bufkit_anal) in bufkit_data.into_iter() {
+            let lead_time = snd.lead_time().into_option().unwrap_or(-1);
+            let valid_time = match snd.valid_time() {
+                Some(valid_time) => valid_time,
+                None => {
+                    println!("{:>5}{:>16}", lead_time, "-");
+                    continue;
+                }
+            };
+            let lcl_time = tz.from_utc_datetime(&valid_time);
+
+            let record = StatsRecord::create_cli_data(
+                site.clone(),
+                *model,
+                valid_time,
+                &snd,
+                &bufkit_anal,
+                &registry,
+            );
+
+            print!("{:>5}{:>16}", lead_time, valid_time.format("%Y-%m-%d %HZ"));
+            for (i, &element) in elements.iter().enumerate() {
+                let val = match record.value(element) {
+                    Some(val) => val,
+                    None => {
+                        print!("{:>22}", "-");
+                        continue;
+                    }
+                };
+
+                // Directions don't have a rank, there is no highest or lowest.
+                if element.is_direction() {
+                    print!("{:>14.1} (  - )", val);
+                    continue;
+                }
+
+                let climo_vals =
+                    climo.values(i, element, lcl_time.date_naive(), lcl_time.hour())?;
+                match bufcli::percentile_rank(val, climo_vals) {
+                    Some(rank) => print!("{:>14.1} ({:>4.1})", val, rank),
+                    None => print!("{:>14.1} (  - )", val),
+                }
+            }
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
+/// The climatology of each element in a window around a local date, split up by local hour.
+///
+/// A model run only covers a few days, so loading each window once instead of for every hour
+/// saves a lot of queries.
+struct ClimoWindows<'a, 'b> {
+    climo_db: &'a ClimoQueryInterface<'b>,
+    site: &'a SiteInfo,
+    model: Model,
+    window_days: u32,
+    /// Keyed by the index of the element and the local date at the center of the window.
+    windows: HashMap<(usize, NaiveDate), Vec<Vec<f64>>>,
+}
+
+impl<'a, 'b> ClimoWindows<'a, 'b> {
+    fn new(
+        climo_db: &'a ClimoQueryInterface<'b>,
+        site: &'a SiteInfo,
+        model: Model,
+        window_days: u32,
+    ) -> Self {
+        ClimoWindows {
+            climo_db,
+            site,
+            model,
+            window_days,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Get the values of the element at a local hour in the window around a local date.
+    fn values(
+        &mut self,
+        index: usize,
+        element: ClimoElement,
+        date: NaiveDate,
+        hour: u32,
+    ) -> Result<&[f64], Box<dyn Error>> {
+        if !self.windows.contains_key(&(index, date)) {
+            let tz = self
+                .site
+                .time_zone
+                .unwrap_or_else(|| FixedOffset::west_opt(0).unwrap());
+            let filter = ClimoQueryFilter::new().with_day_of_year_window(date, self.window_days);
+
+            let mut by_hour: Vec<Vec<f64>> = vec![vec![]; 24];
+            for (valid_time, val) in self
+                .climo_db
+                .time_series(self.site, self.model, element, &filter)?
+            {
+                by_hour[tz.from_utc_datetime(&valid_time).hour() as usize].push(val);
+            }
+
+            self.windows.insert((index, date), by_hour);
+        }
+
+        Ok(&self.windows[&(index, date)][hour as usize])
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{CmdLineArgs, MONTHS, PERCENTILES};
+use bufcli::{
+    BufcliError, ClimoDB, ClimoElement, ClimoQueryInterface, ErrorSummary, ForecastPairs, Summary,
+};
+use bufkit_data::SiteInfo;
+use chrono::{Datelike, FixedOffset, TimeZone};
+use std::error::Error;
+
+pub(crate) fn verify_run(args: CmdLineArgs) -> Result<(), Box<dyn Error>> {
+    let elements = args.selected_elements();
+    if elements.is_empty() {
+        return Err(BufcliError::new("No elements selected to verify, use --elements").into());
+    }
+    if args.lead_times.is_empty() {
+        return Err(BufcliError::new("No lead times selected to verify, use --lead-times").into());
+    }
+
+    let climo_db = ClimoDB::connect_or_create(&args.root)?;
+    let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+    for (site, model) in args.site_model_pairs.iter() {
+        for &element in elements.iter() {
+            let mut all_pairs: Vec<(u32, ForecastPairs)> = vec![];
+            for &lead_time in args.lead_times.iter() {
+                let pairs =
+                    climo_db.forecast_pairs(site, *model, element, lead_time, &args.filter)?;
+                all_pairs.push((lead_time, pairs));
+            }
+
+            println!();
+            println!(
+                "{} - {} - {} - {} verification",
+                site.description(),
+                model,
+                element,
+                args.period
+            );
+
+            print_errors(site, element, &all_pairs);
+            // Percentiles of directions don't mean anything.
+            if !element.is_direction() {
+                print_quantiles(site, &all_pairs);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the errors of the forecasts at each lead time, by local month of the valid time.
+fn print_errors(site: &SiteInfo, element: ClimoElement, all_pairs: &[(u32, ForecastPairs)]) {
+    let errors = |pairs: &[(f64, f64)]| {
+        if element.is_direction() {
+            ErrorSummary::new_directions(pairs)
+        } else {
+            ErrorSummary::new(pairs)
+        }
+    };
+
+    println!(
+        "{:>5}{:>8}{:>8}{:>10}{:>10}{:>10}",
+        "lead", "month", "count", "bias", "mae", "rmse"
+    );
+
+    for (lead_time, pairs) in all_pairs.iter() {
+        for (month, month_pairs) in by_month(site, pairs).iter().enumerate() {
+            if let Some(errors) = errors(month_pairs) {
+                print_error_row(*lead_time, MONTHS[month], &errors);
+            }
+        }
+
+        let pairs: Vec<(f64, f64)> = pairs.iter().map(|&(_, f, a)| (f, a)).collect();
+        match errors(&pairs) {
+            Some(errors) => print_error_row(*lead_time, "all", &errors),
+            None => println!("{:>5}{:>8}{:>8}", lead_time, "all", 0),
+        }
+    }
+}
+
+fn print_error_row(lead_time: u32, month: &str, errors: &ErrorSummary) {
+    println!(
+        "{:>5}{:>8}{:>8}{:>10.2}{:>10.2}{:>10.2}",
+        lead_time,
+        month,
+        errors.count(),
+        errors.bias(),
+        errors.mae(),
+        errors.rmse()
+    );
+}
+
+/// Print the percentiles of the forecasts at each lead time next to those of the analyses they
+/// are paired with, by local month of the valid time.
+fn print_quantiles(site: &SiteInfo, all_pairs: &[(u32, ForecastPairs)]) {
+    println!();
+    print!("{:>5}{:>8}{:>8}", "lead", "month", "");
+    for pct in PERCENTILES.iter() {
+        print!("{:>10}", format!("p{}", pct));
+    }
+    println!();
+
+    for (lead_time, pairs) in all_pairs.iter() {
+        for (month, month_pairs) in by_month(site, pairs).iter().enumerate() {
+            print_quantile_rows(*lead_time, MONTHS[month], month_pairs);
+        }
+
+        let pairs: Vec<(f64, f64)> = pairs.iter().map(|&(_, f, a)| (f, a)).collect();
+        print_quantile_rows(*lead_time, "all", &pairs);
+    }
+}
+
+fn print_quantile_rows(lead_time: u32, month: &str, pairs: &[(f64, f64)]) {
+    let fcst = Summary::new(pairs.iter().map(|&(f, _)| f).collect());
+    let anal = Summary::new(pairs.iter().map(|&(_, a)| a).collect());
+
+    let (fcst, anal) = match (fcst, anal) {
+        (Some(fcst), Some(anal)) => (fcst, anal),
+        _ => return,
+    };
+
+    for (label, summary) in [("fcst", fcst), ("anal", anal)].iter() {
+        print!("{:>5}{:>8}{:>8}", lead_time, month, label);
+        for &pct in PERCENTILES.iter() {
+            print!("{:>10.1}", summary.percentile(pct));
+        }
+        println!();
+    }
+}
+
+/// Split the `(forecast, analysis)` pairs up by the local month of their valid time.
+fn by_month(site: &SiteInfo, pairs: &ForecastPairs) -> Vec<Vec<(f64, f64)>> {
+    let tz = site
+        .time_zone
+        .unwrap_or_else(|| FixedOffset::west_opt(0).unwrap());
+
+    let mut by_month: Vec<Vec<(f64, f64)>> = vec![vec![]; 12];
+    for &(valid_time, fcst, anal) in pairs.iter() {
+        let month = tz.from_utc_datetime(&valid_time).month0() as usize;
+        by_month[month].push((fcst, anal));
+    }
+
+    by_month
+}
@@ -0,0 +1,1567 @@
+use crate::{
+    content_hash, BufcliError, ClimoDB, ClimoElement, ClimoElementRegistry, ClimoPopulateInterface,
+    FileStatus, ProcessedFile, StatsRecord,
+};
+use bufkit_data::{Archive, Model, SiteInfo, StationNumber};
+use chrono::NaiveDateTime;
+use crossbeam_channel::{self as channel, Receiver, Sender};
+use sounding_analysis::Sounding;
+use sounding_bufkit::BufkitData;
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    error::Error,
+    iter::FromIterator,
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+};
+
+// Capacity of bounded channels used in data module.
+const CAPACITY: usize = 256;
+
+/// Fills the climo database from the files in an archive.
+///
+/// The files are loaded, parsed, and analyzed on several threads, and the progress is reported
+/// with `BuildEvent`s as they go.
+#[derive(Clone, Debug)]
+pub struct ClimoBuilder {
+    root: PathBuf,
+    site_model_pairs: Vec<(SiteInfo, Model)>,
+    registry: ClimoElementRegistry,
+    lead_times: Vec<u32>,
+    purge_bad_files: bool,
+}
+
+/// Progress reports from a `ClimoBuilder`.
+#[derive(Debug)]
+pub enum BuildEvent {
+    /// The total number of files in the archive for the sites and models being built.
+    Started { total: u64 },
+    /// The number of files that have been dealt with so far.
+    Progress { num: u64 },
+    /// A file could not be processed. It is only removed from the archive when purging bad files.
+    FileError {
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        status: FileStatus,
+        msg: String,
+        removed: bool,
+    },
+    /// A worker thread failed.
+    ThreadError(String),
+    /// Everything has been written to the database.
+    Finished,
+}
+
+/// What a `ClimoBuilder` does with the files that have already been processed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operation {
+    Build,
+    Update,
+    Recompute,
+}
+
+impl ClimoBuilder {
+    /// Create a builder for the archive at `root` that calculates the elements in `registry`.
+    pub fn new(
+        root: &Path,
+        site_model_pairs: Vec<(SiteInfo, Model)>,
+        registry: ClimoElementRegistry,
+    ) -> Self {
+        ClimoBuilder {
+            root: root.to_path_buf(),
+            site_model_pairs,
+            registry,
+            lead_times: vec![],
+            purge_bad_files: false,
+        }
+    }
+
+    /// Also store the elements for these forecast lead times (hours) of every model run.
+    pub fn with_lead_times(mut self, lead_times: &[u32]) -> Self {
+        self.lead_times = lead_times.to_vec();
+        self
+    }
+
+    /// Remove files that can't be parsed from the archive. Files that were already recorded as
+    /// bad are processed again so they can be removed.
+    pub fn with_purge_bad_files(mut self, purge_bad_files: bool) -> Self {
+        self.purge_bad_files = purge_bad_files;
+        self
+    }
+
+    /// Process every file in the archive.
+    pub fn build<F>(self, events: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(BuildEvent),
+    {
+        self.run(Operation::Build, events)
+    }
+
+    /// Only process the files that are new or have changed since they were processed.
+    pub fn update<F>(self, events: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(BuildEvent),
+    {
+        self.run(Operation::Update, events)
+    }
+
+    /// Calculate `elements` again for the files that are already in the database, leaving the
+    /// other elements alone.
+    pub fn recompute<F>(
+        mut self,
+        elements: &[ClimoElement],
+        events: F,
+    ) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(BuildEvent),
+    {
+        // Recomputing everything is just a build.
+        if elements.is_empty() {
+            return Err(BufcliError::new("No elements selected to recompute").into());
+        }
+        self.registry.select(elements)?;
+
+        self.run(Operation::Recompute, events)
+    }
+
+    fn run<F>(self, operation: Operation, mut events: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(BuildEvent),
+    {
+        use DataPopulateMsg::*;
+
+        let ClimoBuilder {
+            root,
+            site_model_pairs,
+            mut registry,
+            lead_times,
+            purge_bad_files,
+        } = self;
+
+        registry.create_columns(
+            &ClimoDB::connect_or_create(&root)?,
+            operation != Operation::Update,
+        )?;
+
+        let coverage = Coverage {
+            elements: registry
+                .elements()
+                .iter()
+                .map(|elem| elem.column_name().to_owned())
+                .collect(),
+            lead_times: lead_times.clone(),
+        };
+
+        // Channels for the main pipeline
+        let (entry_point_snd, load_requests_rcv) = channel::bounded::<DataPopulateMsg>(CAPACITY);
+        let (parse_requests_snd, parse_requests_rcv) =
+            channel::bounded::<DataPopulateMsg>(CAPACITY);
+        let (cli_requests_snd, cli_requests_rcv) = channel::bounded::<DataPopulateMsg>(CAPACITY);
+        let (loc_requests_snd, loc_requests_rcv) = channel::bounded::<DataPopulateMsg>(CAPACITY);
+        let (comp_notify_snd, comp_notify_rcv) = channel::bounded::<DataPopulateMsg>(CAPACITY);
+
+        // Channel for adding stats to the climo database
+        let (stats_snd, stats_rcv) = channel::bounded::<StatsRecord>(CAPACITY);
+
+        // Hook everything together
+        let stats_jh = start_stats_thread(&root, stats_rcv, comp_notify_snd.clone())?;
+        let total_num = start_entry_point_thread(
+            &root,
+            site_model_pairs,
+            operation,
+            purge_bad_files,
+            coverage.clone(),
+            entry_point_snd,
+        )?;
+        start_load_thread(&root, coverage, load_requests_rcv, parse_requests_snd)?;
+        start_parser_thread(parse_requests_rcv, cli_requests_snd)?;
+        start_cli_stats_thread(
+            registry,
+            cli_requests_rcv,
+            loc_requests_snd,
+            stats_snd.clone(),
+        )?;
+        start_location_stats_thread(loc_requests_rcv, comp_notify_snd, stats_snd)?;
+
+        // Monitor progress and post updates here
+        events(BuildEvent::Started { total: total_num });
+        let arch = Archive::connect(&root)?;
+        for msg in comp_notify_rcv {
+            match msg {
+                PopulateCompleted { num } => {
+                    events(BuildEvent::Progress { num: num as u64 });
+                }
+                // Signals that the stats thread or location stats thread is done.
+                TerminateThread => {}
+                DataError {
+                    num,
+                    site,
+                    model,
+                    init_time,
+                    status,
+                    msg,
+                    ..
+                } => {
+                    // Only files that can't be parsed are bad, the others may just be missing
+                    // some data. Bad files are left in the archive unless asked to purge them.
+                    let removed = purge_bad_files
+                        && status == FileStatus::ParseError
+                        && arch.file_exists(site.station_num, model, init_time)?;
+                    if removed {
+                        arch.remove(site.station_num, model, init_time)?;
+                    }
+
+                    events(BuildEvent::FileError {
+                        site,
+                        model,
+                        init_time,
+                        status,
+                        msg,
+                        removed,
+                    });
+                    events(BuildEvent::Progress { num: num as u64 });
+                }
+                ThreadError(msg) => events(BuildEvent::ThreadError(msg)),
+                _ => events(BuildEvent::ThreadError(format!(
+                    "Invalid message recieved in main thread: {:?}",
+                    msg
+                ))),
+            }
+        }
+
+        // Nothing is finished until the stats thread has written everything to the database.
+        match stats_jh.join() {
+            Ok(Ok(())) => {}
+            Ok(Err(msg)) => {
+                events(BuildEvent::ThreadError(msg));
+                return Err(BufcliError::new("Error writing to the climo database").into());
+            }
+            Err(_) => return Err(BufcliError::new("Climo database writer thread panicked").into()),
+        }
+        events(BuildEvent::Finished);
+
+        Ok(())
+    }
+}
+
+/// The elements, by column name, and forecast lead times stored from a file.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Coverage {
+    elements: Vec<String>,
+    lead_times: Vec<u32>,
+}
+
+impl Coverage {
+    fn of(file: &ProcessedFile) -> Self {
+        Coverage {
+            elements: file.elements.clone(),
+            lead_times: file.lead_times.clone(),
+        }
+    }
+
+    /// What was stored from files processed before it was recorded.
+    fn legacy() -> Self {
+        Coverage {
+            elements: ClimoElement::LEGACY
+                .iter()
+                .map(|elem| elem.column_name().to_owned())
+                .collect(),
+            lead_times: vec![],
+        }
+    }
+
+    /// Whether everything in `other` is also in this.
+    fn covers(&self, other: &Coverage) -> bool {
+        other
+            .elements
+            .iter()
+            .all(|elem| self.elements.contains(elem))
+            && other
+                .lead_times
+                .iter()
+                .all(|lt| self.lead_times.contains(lt))
+    }
+
+    fn union(&self, other: &Coverage) -> Coverage {
+        let mut elements: Vec<String> = self
+            .elements
+            .iter()
+            .chain(other.elements.iter())
+            .cloned()
+            .collect();
+        elements.sort();
+        elements.dedup();
+
+        let mut lead_times: Vec<u32> = self
+            .lead_times
+            .iter()
+            .chain(other.lead_times.iter())
+            .cloned()
+            .collect();
+        lead_times.sort_unstable();
+        lead_times.dedup();
+
+        Coverage {
+            elements,
+            lead_times,
+        }
+    }
+}
+
+/// What is already in the database from a file that is processed again. The hash is `None` if it
+/// is not known.
+#[derive(Debug)]
+struct Prior {
+    hash: Option<String>,
+    coverage: Coverage,
+}
+
+/// A file that is still being processed, with the worst outcome of it so far.
+struct PendingFile {
+    num: usize,
+    site: Option<SiteInfo>,
+    status: FileStatus,
+    hash: Option<String>,
+    reason: Option<String>,
+    coverage: Coverage,
+    /// The number of messages for the file that have been dealt with.
+    done: usize,
+    /// The number of messages for the file, known once the `Processed` message arrives.
+    expected: Option<usize>,
+}
+
+impl PendingFile {
+    fn new(num: usize) -> Self {
+        PendingFile {
+            num,
+            site: None,
+            status: FileStatus::Ok,
+            hash: None,
+            reason: None,
+            coverage: Coverage::default(),
+            done: 0,
+            expected: None,
+        }
+    }
+
+    /// Add an outcome of processing the file. A file can have several, e.g. when some of its
+    /// soundings are bad, so only the worst is kept.
+    fn add_outcome(&mut self, status: FileStatus, reason: Option<String>) {
+        if status > self.status || (status == self.status && self.reason.is_none()) {
+            self.status = status;
+            self.reason = reason;
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.expected == Some(self.done)
+    }
+}
+
+macro_rules! assign_or_bail {
+    ($res:expr, $channel:ident) => {
+        match $res {
+            Ok(val) => val,
+            Err(err) => {
+                $channel
+                    .send(DataPopulateMsg::ThreadError(err.to_string()))
+                    .unwrap_or_else(|err| {
+                        eprintln!("Broken channel, returning from thread with error: {}", err)
+                    });
+                return;
+            }
+        }
+    };
+    ($res:expr, $channel:ident, $msg:expr) => {
+        match $res {
+            Ok(val) => val,
+            Err(err) => {
+                $channel
+                    .send(DataPopulateMsg::ThreadError(err.to_string() + $msg))
+                    .unwrap_or_else(|err| {
+                        eprintln!("Broken channel, returning from thread with error: {}", err)
+                    });
+                return;
+            }
+        }
+    };
+}
+
+macro_rules! send_or_bail {
+    ($msg:ident, $channel:ident) => {
+        match $channel.send($msg) {
+            Ok(()) => {}
+            Err(err) => {
+                eprintln!("Broken channel with error: {}", err);
+                return;
+            }
+        }
+    };
+}
+
+fn start_entry_point_thread(
+    root: &Path,
+    site_model_pairs: Vec<(SiteInfo, Model)>,
+    operation: Operation,
+    purge_bad_files: bool,
+    coverage: Coverage,
+    entry_point_snd: Sender<DataPopulateMsg>,
+) -> Result<u64, Box<dyn Error>> {
+    let root = root.to_path_buf();
+    let arch = Archive::connect(&root)?;
+
+    let mut total = 0;
+    for (site_info, model) in site_model_pairs.iter() {
+        total += arch.count(site_info.station_num, *model)? as u64;
+    }
+
+    thread::Builder::new()
+        .name("Generator".to_string())
+        .spawn(move || {
+            let force_rebuild = operation == Operation::Build;
+
+            let arch = assign_or_bail!(
+                Archive::connect(&root),
+                entry_point_snd,
+                " error connecting to archive"
+            );
+            let climo_db = assign_or_bail!(
+                ClimoDB::connect_or_create(&root),
+                entry_point_snd,
+                " error connecting to climo db"
+            );
+            let mut climo_db = assign_or_bail!(
+                ClimoPopulateInterface::initialize(&climo_db),
+                entry_point_snd,
+                " error connecting to ClimoPopulateInterface"
+            );
+
+            let mut counter = 0;
+            for (site, model) in site_model_pairs.into_iter() {
+                let init_times = assign_or_bail!(
+                    arch.inventory(site.station_num, model),
+                    entry_point_snd,
+                    " error retrieving init_times"
+                );
+                let init_times: HashSet<NaiveDateTime> = HashSet::from_iter(init_times);
+
+                let processed: HashMap<NaiveDateTime, ProcessedFile> = if !force_rebuild {
+                    let files = assign_or_bail!(
+                        climo_db.processed_files_for(&site, model),
+                        entry_point_snd,
+                        " error retrieving processed files"
+                    );
+                    files
+                        .into_iter()
+                        .map(|file| (file.init_time, file))
+                        .collect()
+                } else {
+                    HashMap::new()
+                };
+
+                // Databases built before files were tracked, or runs that stopped before the
+                // outcome of a file was recorded, leave analyses from files that are not in
+                // processed_files. They are taken as processed with the legacy elements.
+                let valid_times: HashSet<NaiveDateTime> =
+                    if force_rebuild || init_times.iter().all(|t| processed.contains_key(t)) {
+                        HashSet::new()
+                    } else {
+                        let iter = assign_or_bail!(
+                            climo_db.valid_times_for(&site, model),
+                            entry_point_snd,
+                            " error retrieving valid times"
+                        );
+                        HashSet::from_iter(iter)
+                    };
+                let todo = plan_files(
+                    operation,
+                    purge_bad_files,
+                    &coverage,
+                    &init_times,
+                    &processed,
+                    &valid_times,
+                );
+
+                let mut small_counter = 0;
+                for (init_time, action, prior) in todo {
+                    counter += 1;
+                    small_counter += 1;
+
+                    let message = DataPopulateMsg::Load {
+                        model,
+                        init_time,
+                        site: site.clone(),
+                        num: counter,
+                        action,
+                        prior,
+                    };
+
+                    send_or_bail!(message, entry_point_snd);
+                }
+
+                counter += init_times.len() - small_counter;
+            }
+        })?;
+
+    Ok(total)
+}
+
+/// Decide what to do with each of the files at `init_times` for a site and model.
+///
+/// `processed` are the files recorded in the database, and `valid_times` the times of the analyses
+/// stored for the site and model, which show the files processed before they were recorded.
+fn plan_files(
+    operation: Operation,
+    purge_bad_files: bool,
+    coverage: &Coverage,
+    init_times: &HashSet<NaiveDateTime>,
+    processed: &HashMap<NaiveDateTime, ProcessedFile>,
+    valid_times: &HashSet<NaiveDateTime>,
+) -> Vec<(NaiveDateTime, LoadAction, Option<Prior>)> {
+    let is_legacy = |init_time: &NaiveDateTime| {
+        !processed.contains_key(init_time) && valid_times.contains(init_time)
+    };
+
+    let legacy = || Prior {
+        hash: None,
+        coverage: Coverage::legacy(),
+    };
+    let prior = |file: &ProcessedFile| Prior {
+        hash: file.hash.clone(),
+        coverage: Coverage::of(file),
+    };
+
+    if operation == Operation::Recompute {
+        // Recomputing only updates files that are already in the database.
+        return init_times
+            .iter()
+            .filter_map(|&init_time| match processed.get(&init_time) {
+                Some(file) if file.status != FileStatus::ParseError => {
+                    Some((init_time, LoadAction::Process, Some(prior(file))))
+                }
+                Some(_) => None,
+                None if is_legacy(&init_time) => {
+                    Some((init_time, LoadAction::Process, Some(legacy())))
+                }
+                None => None,
+            })
+            .collect();
+    }
+
+    init_times
+        .iter()
+        .map(|&init_time| match processed.get(&init_time) {
+            // Bad files are processed again when purging so they can be removed.
+            Some(file) if file.status == FileStatus::ParseError && purge_bad_files => {
+                (init_time, LoadAction::Process, None)
+            }
+            Some(ProcessedFile {
+                status: FileStatus::ParseError,
+                hash: Some(hash),
+                ..
+            }) => (init_time, LoadAction::IfChanged(hash.clone()), None),
+            // Files are processed again if more elements or lead times are selected than were
+            // stored from them.
+            Some(file) => match file.hash {
+                Some(ref hash) if Coverage::of(file).covers(coverage) => (
+                    init_time,
+                    LoadAction::IfChanged(hash.clone()),
+                    Some(prior(file)),
+                ),
+                Some(_) => (init_time, LoadAction::Process, Some(prior(file))),
+                None => (init_time, LoadAction::Process, None),
+            },
+            None if is_legacy(&init_time) => {
+                if Coverage::legacy().covers(coverage) {
+                    (init_time, LoadAction::Adopt, None)
+                } else {
+                    (init_time, LoadAction::Process, Some(legacy()))
+                }
+            }
+            None => (init_time, LoadAction::Process, None),
+        })
+        .collect()
+}
+
+/// Get the forecast lead times to store from a file, and what will be stored from it once they
+/// are, when storing what `current` selects.
+///
+/// What was stored from the file before is still there if the file hasn't changed. Its lead times
+/// are stored again so the forecasts there get the current elements too.
+fn processing_plan(prior: Option<Prior>, hash: &str, current: &Coverage) -> (Vec<u32>, Coverage) {
+    let prior = match prior {
+        Some(prior) if prior.hash.as_ref().map(|h| h == hash).unwrap_or(true) => prior.coverage,
+        _ => return (current.lead_times.clone(), current.clone()),
+    };
+
+    let lead_times = prior.union(current).lead_times;
+
+    // Only the current elements are stored at new lead times.
+    let stored = if current
+        .lead_times
+        .iter()
+        .all(|lt| prior.lead_times.contains(lt))
+    {
+        Coverage {
+            elements: prior.union(current).elements,
+            lead_times: prior.lead_times,
+        }
+    } else {
+        Coverage {
+            elements: current.elements.clone(),
+            lead_times: lead_times.clone(),
+        }
+    };
+
+    (lead_times, stored)
+}
+
+fn start_load_thread(
+    root: &Path,
+    coverage: Coverage,
+    load_requests_rcv: Receiver<DataPopulateMsg>,
+    parse_requests_snd: Sender<DataPopulateMsg>,
+) -> Result<(), Box<dyn Error>> {
+    let root = root.to_path_buf();
+
+    thread::Builder::new()
+        .name("FileLoader".to_string())
+        .spawn(move || {
+            let arch = assign_or_bail!(
+                Archive::connect(&root),
+                parse_requests_snd,
+                " error connecting in FileLoader"
+            );
+
+            for load_req in load_requests_rcv {
+                let message = match load_req {
+                    DataPopulateMsg::Load {
+                        num,
+                        site,
+                        model,
+                        init_time,
+                        action,
+                        prior,
+                    } => match arch.retrieve(site.station_num, model, init_time) {
+                        Ok(data) => {
+                            let hash = content_hash(&data);
+
+                            let (lead_times, stored) = processing_plan(prior, &hash, &coverage);
+
+                            match action {
+                                // Files that haven't changed since they were processed are done.
+                                LoadAction::IfChanged(known_hash) if known_hash == hash => {
+                                    DataPopulateMsg::PopulateCompleted { num }
+                                }
+                                LoadAction::Adopt => DataPopulateMsg::Processed {
+                                    num,
+                                    site,
+                                    model,
+                                    init_time,
+                                    hash: Some(hash),
+                                    status: FileStatus::Ok,
+                                    coverage: Coverage::legacy(),
+                                    num_msgs: 0,
+                                },
+                                _ => DataPopulateMsg::Parse {
+                                    num,
+                                    site,
+                                    model,
+                                    init_time,
+                                    data,
+                                    hash,
+                                    lead_times,
+                                    coverage: stored,
+                                },
+                            }
+                        }
+                        Err(err) => {
+                            let message = DataPopulateMsg::DataError {
+                                num,
+                                site: site.clone(),
+                                model,
+                                init_time,
+                                status: FileStatus::ParseError,
+                                msg: err.to_string() + " in data load thread",
+                            };
+                            send_or_bail!(message, parse_requests_snd);
+
+                            DataPopulateMsg::Processed {
+                                num,
+                                site,
+                                model,
+                                init_time,
+                                hash: None,
+                                status: FileStatus::ParseError,
+                                coverage: coverage.clone(),
+                                num_msgs: 1,
+                            }
+                        }
+                    },
+                    message => message,
+                };
+
+                send_or_bail!(message, parse_requests_snd);
+            }
+        })?;
+
+    Ok(())
+}
+
+fn start_parser_thread(
+    parse_requests: Receiver<DataPopulateMsg>,
+    cli_requests: Sender<DataPopulateMsg>,
+) -> Result<(), Box<dyn Error>> {
+    thread::Builder::new()
+        .name("SoundingParser".to_string())
+        .spawn(move || {
+            for msg in parse_requests {
+                if let DataPopulateMsg::Parse {
+                    num,
+                    site,
+                    model,
+                    init_time,
+                    data,
+                    hash,
+                    lead_times,
+                    coverage,
+                } = msg
+                {
+                    let bufkit_data = match BufkitData::init(&data, "") {
+                        Ok(bufkit_data) => bufkit_data,
+                        Err(err) => {
+                            let message = DataPopulateMsg::DataError {
+                                num,
+                                site: site.clone(),
+                                model,
+                                init_time,
+                                status: FileStatus::ParseError,
+                                msg: err.to_string().trim_end().to_owned()
+                                    + " in data parse thread",
+                            };
+                            send_or_bail!(message, cli_requests);
+
+                            let message = DataPopulateMsg::Processed {
+                                num,
+                                site,
+                                model,
+                                init_time,
+                                hash: Some(hash),
+                                status: FileStatus::ParseError,
+                                coverage,
+                                num_msgs: 1,
+                            };
+                            send_or_bail!(message, cli_requests);
+                            continue;
+                        }
+                    };
+
+                    // Keep the analysis window plus any requested forecast lead times.
+                    let max_lead_time = lead_times
+                        .iter()
+                        .map(|&lt| i64::from(lt) + 1)
+                        .fold(model.hours_between_runs(), i64::max);
+
+                    // The messages sent for this file, so the location stats thread knows when
+                    // they have all been dealt with.
+                    let mut num_msgs = 0;
+                    let mut num_analyses = 0;
+                    let mut num_forecasts = 0;
+                    let mut num_without_valid_time = 0;
+                    for (snd, bufkit_anal) in bufkit_data.into_iter().take_while(|(snd, _)| {
+                        snd.lead_time()
+                            .into_option()
+                            .map(|lt| i64::from(lt) < max_lead_time)
+                            .unwrap_or(false)
+                    }) {
+                        let lead_time = snd.lead_time().into_option().unwrap_or(-1);
+
+                        if let Some(lead_time) = u32::try_from(lead_time)
+                            .ok()
+                            .filter(|lt| lead_times.contains(lt))
+                        {
+                            let message = DataPopulateMsg::FcstData {
+                                site: site.clone(),
+                                model,
+                                init_time,
+                                lead_time,
+                                snd: Box::new(snd.clone()),
+                                bufkit_anal: bufkit_anal.clone(),
+                            };
+                            send_or_bail!(message, cli_requests);
+                            num_msgs += 1;
+                            num_forecasts += 1;
+                        }
+
+                        if i64::from(lead_time) >= model.hours_between_runs() {
+                            continue;
+                        }
+
+                        if let Some(valid_time) = snd.valid_time() {
+                            num_analyses += 1;
+
+                            let message = DataPopulateMsg::CliData {
+                                num,
+                                site: site.clone(),
+                                model,
+                                init_time,
+                                valid_time,
+                                snd: Box::new(snd),
+                                bufkit_anal,
+                            };
+                            send_or_bail!(message, cli_requests);
+                        } else {
+                            // Just skip the sounding, the rest of the file may be fine.
+                            num_without_valid_time += 1;
+
+                            let message = DataPopulateMsg::DataError {
+                                num,
+                                site: site.clone(),
+                                model,
+                                init_time,
+                                status: FileStatus::Skipped,
+                                msg: "No valid time in parse thread".to_string(),
+                            };
+
+                            send_or_bail!(message, cli_requests);
+                        }
+                        num_msgs += 1;
+                    }
+
+                    // A file without any analyses has nothing to add to the climatology, and it
+                    // is bad if none of its soundings could be used.
+                    let status = if num_analyses > 0 {
+                        FileStatus::Ok
+                    } else if num_forecasts == 0 && num_without_valid_time > 0 {
+                        FileStatus::ParseError
+                    } else {
+                        FileStatus::Skipped
+                    };
+                    let message = DataPopulateMsg::Processed {
+                        num,
+                        site,
+                        model,
+                        init_time,
+                        hash: Some(hash),
+                        status,
+                        coverage,
+                        num_msgs,
+                    };
+                    send_or_bail!(message, cli_requests);
+                } else {
+                    send_or_bail!(msg, cli_requests);
+                }
+            }
+        })?;
+
+    Ok(())
+}
+
+fn start_cli_stats_thread(
+    registry: ClimoElementRegistry,
+    cli_requests: Receiver<DataPopulateMsg>,
+    location_requests: Sender<DataPopulateMsg>,
+    climo_update_requests: Sender<StatsRecord>,
+) -> Result<(), Box<dyn Error>> {
+    thread::Builder::new()
+        .name("CliStatsBuilder".to_string())
+        .spawn(move || {
+            const POOL_SIZE: usize = 12;
+
+            let pool = threadpool::Builder::new()
+                .num_threads(POOL_SIZE)
+                .thread_name("CliStatsCalc".to_string())
+                .build();
+
+            for _ in 0..POOL_SIZE {
+                let local_cli_requests = cli_requests.clone();
+                let local_location_requests = location_requests.clone();
+                let local_update_requests = climo_update_requests.clone();
+                let local_registry = registry.clone();
+
+                pool.execute(move || {
+                    for msg in local_cli_requests {
+                        match msg {
+                            DataPopulateMsg::CliData {
+                                num,
+                                site,
+                                model,
+                                init_time,
+                                valid_time,
+                                snd,
+                                bufkit_anal,
+                            } => {
+                                {
+                                    let message = StatsRecord::create_cli_data(
+                                        site.clone(),
+                                        model,
+                                        valid_time,
+                                        &snd,
+                                        &bufkit_anal,
+                                        &local_registry,
+                                    );
+                                    send_or_bail!(message, local_update_requests);
+                                }
+
+                                let message = DataPopulateMsg::Location {
+                                    num,
+                                    site,
+                                    model,
+                                    init_time,
+                                    snd,
+                                };
+                                send_or_bail!(message, local_location_requests);
+                            }
+                            // Forecasts have nothing to add to the locations.
+                            DataPopulateMsg::FcstData {
+                                site,
+                                model,
+                                init_time,
+                                lead_time,
+                                snd,
+                                bufkit_anal,
+                            } => {
+                                let station_num = site.station_num;
+
+                                let message = StatsRecord::create_fcst_data(
+                                    site,
+                                    model,
+                                    init_time,
+                                    lead_time,
+                                    &snd,
+                                    &bufkit_anal,
+                                    &local_registry,
+                                );
+                                send_or_bail!(message, local_update_requests);
+
+                                let message = DataPopulateMsg::FcstStored {
+                                    station_num,
+                                    model,
+                                    init_time,
+                                };
+                                send_or_bail!(message, local_location_requests);
+                            }
+                            msg => send_or_bail!(msg, local_location_requests),
+                        }
+                    }
+                });
+            }
+
+            pool.join();
+        })?;
+
+    Ok(())
+}
+
+/// Add the locations to the database, and record the outcome of each file once all of its
+/// messages have been dealt with, which is after all of its data has been sent to the stats thread.
+fn start_location_stats_thread(
+    location_requests: Receiver<DataPopulateMsg>,
+    completed_notification: Sender<DataPopulateMsg>,
+    climo_update_requests: Sender<StatsRecord>,
+) -> Result<(), Box<dyn Error>> {
+    thread::Builder::new()
+        .name("LocationUpdater".to_string())
+        .spawn(move || {
+            const MISSING_LOCATION: &str = "Missing location information in location stats thread";
+
+            let mut pending: HashMap<(StationNumber, Model, NaiveDateTime), PendingFile> =
+                HashMap::new();
+
+            for msg in location_requests {
+                let key = match msg {
+                    DataPopulateMsg::Location {
+                        num,
+                        site,
+                        model,
+                        init_time,
+                        snd,
+                    } => {
+                        let key = (site.station_num, model, init_time);
+                        let file = pending.entry(key).or_insert_with(|| PendingFile::new(num));
+                        file.done += 1;
+
+                        if snd
+                            .lead_time()
+                            .into_option()
+                            .map(|lt| lt == 0)
+                            .unwrap_or(true)
+                        {
+                            match StatsRecord::create_location_data(site, model, &snd) {
+                                Ok(msg) => send_or_bail!(msg, climo_update_requests),
+                                Err(site) => {
+                                    let msg = MISSING_LOCATION.to_string();
+                                    file.add_outcome(FileStatus::Skipped, Some(msg.clone()));
+
+                                    let message = DataPopulateMsg::DataError {
+                                        num,
+                                        site,
+                                        model,
+                                        init_time,
+                                        status: FileStatus::Skipped,
+                                        msg,
+                                    };
+                                    send_or_bail!(message, completed_notification);
+                                }
+                            }
+                        }
+
+                        key
+                    }
+                    DataPopulateMsg::FcstStored {
+                        station_num,
+                        model,
+                        init_time,
+                    } => {
+                        let key = (station_num, model, init_time);
+                        // The file number is set by its Processed message, which can't have been
+                        // dealt with yet if this is the first of its messages.
+                        pending
+                            .entry(key)
+                            .or_insert_with(|| PendingFile::new(0))
+                            .done += 1;
+
+                        key
+                    }
+                    DataPopulateMsg::DataError {
+                        num,
+                        site,
+                        model,
+                        init_time,
+                        status,
+                        msg,
+                    } => {
+                        let key = (site.station_num, model, init_time);
+                        let file = pending.entry(key).or_insert_with(|| PendingFile::new(num));
+                        file.done += 1;
+                        file.add_outcome(status, Some(msg.clone()));
+
+                        let message = DataPopulateMsg::DataError {
+                            num,
+                            site,
+                            model,
+                            init_time,
+                            status,
+                            msg,
+                        };
+                        send_or_bail!(message, completed_notification);
+
+                        key
+                    }
+                    DataPopulateMsg::Processed {
+                        num,
+                        site,
+                        model,
+                        init_time,
+                        hash,
+                        status,
+                        coverage,
+                        num_msgs,
+                    } => {
+                        let key = (site.station_num, model, init_time);
+                        let file = pending.entry(key).or_insert_with(|| PendingFile::new(num));
+                        file.num = num;
+                        file.site = Some(site);
+                        file.hash = hash;
+                        file.coverage = coverage;
+                        file.expected = Some(num_msgs);
+                        file.add_outcome(status, None);
+
+                        key
+                    }
+                    msg => {
+                        send_or_bail!(msg, completed_notification);
+                        continue;
+                    }
+                };
+
+                if pending.get(&key).map(PendingFile::is_complete) != Some(true) {
+                    continue;
+                }
+
+                if let Some(file) = pending.remove(&key) {
+                    let (_, model, init_time) = key;
+
+                    if let Some(site) = file.site {
+                        let record = StatsRecord::File {
+                            site,
+                            model,
+                            init_time,
+                            status: file.status,
+                            hash: file.hash,
+                            reason: file.reason,
+                            elements: file.coverage.elements,
+                            lead_times: file.coverage.lead_times,
+                        };
+                        send_or_bail!(record, climo_update_requests);
+                    }
+
+                    let message = DataPopulateMsg::PopulateCompleted { num: file.num };
+                    send_or_bail!(message, completed_notification);
+                }
+            }
+
+            completed_notification
+                .send(DataPopulateMsg::TerminateThread)
+                .expect("Error sending terminate thread.");
+        })?;
+
+    Ok(())
+}
+
+fn start_stats_thread(
+    root: &Path,
+    stats_rcv: Receiver<StatsRecord>,
+    comp_notify_snd: Sender<DataPopulateMsg>,
+) -> Result<JoinHandle<Result<(), String>>, Box<dyn Error + 'static>> {
+    let root = root.to_path_buf();
+
+    let jh = thread::Builder::new()
+        .name("ClimoWriter".to_string())
+        .spawn(move || {
+            let climo_db = ClimoDB::connect_or_create(&root).map_err(|err| err.to_string())?;
+            let mut climo_db =
+                ClimoPopulateInterface::initialize(&climo_db).map_err(|err| err.to_string())?;
+
+            for msg in stats_rcv {
+                climo_db.add(msg).map_err(|err| err.to_string())?;
+            }
+
+            climo_db.finish().map_err(|err| err.to_string())?;
+
+            comp_notify_snd
+                .send(DataPopulateMsg::TerminateThread)
+                .expect("Error sending terminate thread.");
+
+            Ok(())
+        })?;
+
+    Ok(jh)
+}
+
+/// What the file loader does with a file.
+#[derive(Debug)]
+enum LoadAction {
+    Process,
+    /// Only process the file if the hash of its contents is not this one.
+    IfChanged(String),
+    /// Record the hash of the file without processing it again, because its data is already in
+    /// the database.
+    Adopt,
+}
+
+#[derive(Debug)]
+enum DataPopulateMsg {
+    Load {
+        num: usize,
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        action: LoadAction,
+        prior: Option<Prior>,
+    },
+    Parse {
+        num: usize,
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        data: String,
+        hash: String,
+        /// The forecast lead times to store.
+        lead_times: Vec<u32>,
+        /// What will be stored from the file once it is processed.
+        coverage: Coverage,
+    },
+    CliData {
+        num: usize,
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        valid_time: NaiveDateTime,
+        snd: Box<Sounding>,
+        bufkit_anal: HashMap<&'static str, f64>,
+    },
+    FcstData {
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        lead_time: u32,
+        snd: Box<Sounding>,
+        bufkit_anal: HashMap<&'static str, f64>,
+    },
+    Location {
+        num: usize,
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        snd: Box<Sounding>,
+    },
+    PopulateCompleted {
+        num: usize,
+    },
+    /// Stored the forecast data from a sounding.
+    FcstStored {
+        station_num: StationNumber,
+        model: Model,
+        init_time: NaiveDateTime,
+    },
+    /// The last message for a file, with the number of messages sent for it before this one.
+    Processed {
+        num: usize,
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        hash: Option<String>,
+        status: FileStatus,
+        coverage: Coverage,
+        num_msgs: usize,
+    },
+    DataError {
+        num: usize,
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        status: FileStatus,
+        msg: String,
+    },
+    ThreadError(String),
+    TerminateThread,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClimoQueryFilter, ClimoQueryInterface};
+    use chrono::NaiveDate;
+    use rusqlite::Connection;
+
+    fn time(hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2017, 4, 1)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn coverage(elements: &[&str], lead_times: &[u32]) -> Coverage {
+        Coverage {
+            elements: elements.iter().map(|&elem| elem.to_owned()).collect(),
+            lead_times: lead_times.to_vec(),
+        }
+    }
+
+    fn processed(hour: u32, status: FileStatus, coverage: &Coverage) -> ProcessedFile {
+        ProcessedFile {
+            init_time: time(hour),
+            status,
+            hash: Some(format!("hash{}", hour)),
+            elements: coverage.elements.clone(),
+            lead_times: coverage.lead_times.clone(),
+        }
+    }
+
+    #[test]
+    fn test_coverage_covers() {
+        let stored = coverage(&["hdw", "pft"], &[12, 24]);
+
+        assert!(stored.covers(&coverage(&["pft"], &[24])));
+        assert!(stored.covers(&coverage(&[], &[])));
+        assert!(!stored.covers(&coverage(&["dcape"], &[])));
+        assert!(!stored.covers(&coverage(&["hdw"], &[36])));
+
+        assert!(
+            Coverage::legacy().covers(&coverage(&["hdw", "el_blow_up_dt", "pft", "dcape"], &[]))
+        );
+        assert!(!Coverage::legacy().covers(&coverage(&["vent_index"], &[])));
+
+        assert_eq!(
+            stored.union(&coverage(&["dcape", "hdw"], &[6])),
+            coverage(&["dcape", "hdw", "pft"], &[6, 12, 24])
+        );
+    }
+
+    #[test]
+    fn test_processing_plan() {
+        let prior = |hash: Option<&str>, coverage: Coverage| {
+            Some(Prior {
+                hash: hash.map(ToOwned::to_owned),
+                coverage,
+            })
+        };
+        let current = coverage(&["hdw"], &[24]);
+
+        // New and changed files only get what is selected now.
+        assert_eq!(
+            processing_plan(None, "abc", &current),
+            (vec![24], current.clone())
+        );
+        assert_eq!(
+            processing_plan(
+                prior(Some("def"), coverage(&["pft"], &[12])),
+                "abc",
+                &current
+            ),
+            (vec![24], current.clone())
+        );
+
+        // The stored lead times get the new elements too.
+        assert_eq!(
+            processing_plan(
+                prior(Some("abc"), coverage(&["pft"], &[12, 24])),
+                "abc",
+                &current
+            ),
+            (vec![12, 24], coverage(&["hdw", "pft"], &[12, 24]))
+        );
+
+        // The old elements are not at new lead times.
+        assert_eq!(
+            processing_plan(
+                prior(Some("abc"), coverage(&["pft"], &[12])),
+                "abc",
+                &current
+            ),
+            (vec![12, 24], coverage(&["hdw"], &[12, 24]))
+        );
+
+        // Recomputing without lead times still recomputes the stored ones.
+        assert_eq!(
+            processing_plan(
+                prior(Some("abc"), coverage(&["hdw", "pft"], &[12])),
+                "abc",
+                &coverage(&["pft"], &[])
+            ),
+            (vec![12], coverage(&["hdw", "pft"], &[12]))
+        );
+
+        // Legacy files have no hash.
+        assert_eq!(
+            processing_plan(prior(None, Coverage::legacy()), "abc", &current),
+            (vec![24], coverage(&["hdw"], &[24]))
+        );
+    }
+
+    #[test]
+    fn test_plan_files_update() {
+        let current = coverage(&["hdw", "pft"], &[]);
+
+        let init_times: HashSet<NaiveDateTime> = (0..6).map(time).collect();
+        let processed: HashMap<NaiveDateTime, ProcessedFile> = vec![
+            processed(0, FileStatus::Ok, &current),
+            processed(1, FileStatus::Ok, &coverage(&["hdw"], &[])),
+            processed(2, FileStatus::ParseError, &coverage(&[], &[])),
+        ]
+        .into_iter()
+        .map(|file| (file.init_time, file))
+        .collect();
+        // Analyses from files processed before they were recorded.
+        let valid_times: HashSet<NaiveDateTime> = vec![time(0), time(3)].into_iter().collect();
+
+        let plan: HashMap<NaiveDateTime, (LoadAction, Option<Prior>)> = plan_files(
+            Operation::Update,
+            false,
+            &current,
+            &init_times,
+            &processed,
+            &valid_times,
+        )
+        .into_iter()
+        .map(|(init_time, action, prior)| (init_time, (action, prior)))
+        .collect();
+        assert_eq!(plan.len(), 6);
+
+        // Everything is already stored from this one unless it changed.
+        assert!(
+            matches!(&plan[&time(0)], (LoadAction::IfChanged(hash), Some(_)) if hash == "hash0")
+        );
+
+        // This one is missing the PFT.
+        match &plan[&time(1)] {
+            (LoadAction::Process, Some(prior)) => {
+                assert_eq!(prior.hash.as_deref(), Some("hash1"));
+                assert_eq!(prior.coverage, coverage(&["hdw"], &[]));
+            }
+            other => panic!("unexpected plan {:?}", other),
+        }
+
+        // Bad files are only tried again if they changed.
+        assert!(matches!(&plan[&time(2)], (LoadAction::IfChanged(hash), None) if hash == "hash2"));
+
+        // Legacy files have what older versions stored, which covers these elements.
+        assert!(matches!(&plan[&time(3)], (LoadAction::Adopt, None)));
+
+        assert!(matches!(&plan[&time(4)], (LoadAction::Process, None)));
+        assert!(matches!(&plan[&time(5)], (LoadAction::Process, None)));
+
+        // With purging, bad files are processed again so they can be removed.
+        let plan = plan_files(
+            Operation::Update,
+            true,
+            &current,
+            &init_times,
+            &processed,
+            &valid_times,
+        );
+        assert!(plan
+            .iter()
+            .any(|(t, action, _)| *t == time(2) && matches!(action, LoadAction::Process)));
+    }
+
+    #[test]
+    fn test_plan_files_legacy() {
+        let init_times: HashSet<NaiveDateTime> = vec![time(0)].into_iter().collect();
+        let valid_times = init_times.clone();
+
+        // Elements older versions didn't store are filled in.
+        for operation in [Operation::Update, Operation::Recompute] {
+            let plan = plan_files(
+                operation,
+                false,
+                &coverage(&["hdw", "vent_index"], &[]),
+                &init_times,
+                &HashMap::new(),
+                &valid_times,
+            );
+
+            match plan.as_slice() {
+                [(_, LoadAction::Process, Some(prior))] => {
+                    assert_eq!(prior.hash, None);
+                    assert_eq!(prior.coverage, Coverage::legacy());
+                }
+                other => panic!("unexpected plan {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_files_recompute() {
+        let current = coverage(&["hdw"], &[]);
+
+        let init_times: HashSet<NaiveDateTime> = (0..3).map(time).collect();
+        let processed: HashMap<NaiveDateTime, ProcessedFile> = vec![
+            processed(0, FileStatus::Ok, &current),
+            processed(1, FileStatus::ParseError, &coverage(&[], &[])),
+        ]
+        .into_iter()
+        .map(|file| (file.init_time, file))
+        .collect();
+
+        // Only the files already in the database are recomputed.
+        let plan = plan_files(
+            Operation::Recompute,
+            false,
+            &current,
+            &init_times,
+            &processed,
+            &HashSet::new(),
+        );
+        match plan.as_slice() {
+            [(init_time, LoadAction::Process, Some(_))] => assert_eq!(*init_time, time(0)),
+            other => panic!("unexpected plan {:?}", other),
+        }
+    }
+
+    /// An archive in a temporary directory with the test file in it.
+    struct TestArchive {
+        root: PathBuf,
+        site: SiteInfo,
+    }
+
+    impl TestArchive {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("bufcli-{}-{}", name, std::process::id()));
+            if root.exists() {
+                std::fs::remove_dir_all(&root).unwrap();
+            }
+
+            let arch = Archive::create(&root).unwrap();
+            let station_num = arch
+                .add(
+                    "kmso",
+                    None,
+                    None,
+                    Model::GFS,
+                    include_str!("../test_data/2017040106Z_gfs_kmso.buf"),
+                )
+                .unwrap();
+            // Sites without a name are filtered out before building.
+            let site = SiteInfo {
+                name: Some("Missoula".to_owned()),
+                ..arch.site(station_num).unwrap()
+            };
+
+            TestArchive { root, site }
+        }
+    }
+
+    impl Drop for TestArchive {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    #[test]
+    fn test_update_fills_in_legacy_database() {
+        let arch = TestArchive::new("legacy");
+        let station_num: u32 = arch.site.station_num.into();
+
+        // A database from before the schema was versioned, with the analysis from the file.
+        {
+            std::fs::create_dir(arch.root.join(ClimoDB::CLIMO_DIR)).unwrap();
+            let conn = Connection::open(arch.root.join(ClimoDB::CLIMO_DIR).join(ClimoDB::CLIMO_DB))
+                .unwrap();
+            conn.execute_batch(include_str!("climo_db/create_climate_data_db.sql"))
+                .unwrap();
+            conn.execute(
+                concat!(
+                    "INSERT INTO cli (station_num, model, valid_time, year_lcl, month_lcl,",
+                    " day_lcl, hour_lcl, hdw, el_blow_up_dt, pft, dcape)",
+                    " VALUES (?1, 'gfs', ?2, 2017, 4, 1, 6, 42, 3.5, 100, 250)"
+                ),
+                rusqlite::params![station_num, time(6)],
+            )
+            .unwrap();
+        }
+
+        ClimoBuilder::new(
+            &arch.root,
+            vec![(arch.site.clone(), Model::GFS)],
+            ClimoElementRegistry::new(),
+        )
+        .update(|event| {
+            if let BuildEvent::ThreadError(msg) = event {
+                panic!("{}", msg);
+            }
+        })
+        .unwrap();
+
+        let climo_db = ClimoDB::connect_or_create(&arch.root).unwrap();
+        let mut populate = ClimoPopulateInterface::initialize(&climo_db).unwrap();
+        let files = populate
+            .processed_files_for(&arch.site, Model::GFS)
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].status, FileStatus::Ok);
+        for elem in ClimoElement::ALL.iter() {
+            assert!(
+                files[0]
+                    .elements
+                    .iter()
+                    .any(|col| col == elem.column_name()),
+                "{} not recorded",
+                elem
+            );
+        }
+
+        // The elements the legacy database didn't have are filled in.
+        let climo_db = ClimoQueryInterface::initialize(&climo_db);
+        let filter = ClimoQueryFilter::new();
+        for elem in [ClimoElement::VentilationIndex, ClimoElement::PW] {
+            let values = climo_db
+                .time_series(&arch.site, Model::GFS, elem, &filter)
+                .unwrap();
+            assert!(values.iter().any(|&(valid_time, _)| valid_time == time(6)));
+        }
+    }
+}
@@ -1,5 +1,6 @@
+use crate::BufcliError;
 use rusqlite::{Connection, OpenFlags};
-use std::{error::Error, path::Path};
+use std::{error::Error, path::Path, str::FromStr, time::Duration};
 
 pub struct ClimoDB {
     conn: Connection,
@@ -27,24 +28,595 @@ impl ClimoDB {
             OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
         )?;
 
-        // Create the database if it doesn't exist.
+        // Other threads may hold the database when the create script runs.
+        conn.busy_timeout(Duration::from_secs(20))?;
+
+        Self::initialize(conn)
+    }
+
+    /// Create an empty database in memory.
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Self {
+        Self::initialize(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn initialize(conn: Connection) -> Result<Self, Box<dyn Error>> {
+        // Create the database if it doesn't exist, then bring it up to date.
         conn.execute_batch(include_str!("climo_db/create_climate_data_db.sql"))?;
+        migrate::migrate(&conn)?;
+        migrate::add_element_columns(&conn, ClimoElement::ALL)?;
 
         Ok(ClimoDB { conn })
     }
+
+    /// Get the version of the database schema, as stored with `PRAGMA user_version`.
+    pub fn schema_version(&self) -> Result<i64, Box<dyn Error>> {
+        migrate::schema_version(&self.conn)
+    }
 }
 
 /// Elements we can query for climo data.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ClimoElement {
+    /// Hot-Dry-Windy index.
     HDW,
+    /// Warming required for the equilibrium level of a plume to blow up (C).
     BlowUpDt,
+    /// Change in height of the plume equilibrium level when it blows up (m).
     BlowUpHeight,
+    /// Warming required for a plume to form a cloud (C).
+    BlowUpCloudDt,
+    /// Maximum integrated buoyancy of a plume after it blows up (J/kg).
+    BlowUpMIB,
+    /// Portion of the maximum integrated buoyancy due to latent heat release.
+    BlowUpPctWet,
+    /// Pyrocumulonimbus firepower threshold (GW).
+    PFT,
+    /// Downdraft CAPE (J/kg).
     DCAPE,
+    /// Surface based CAPE (J/kg).
+    SBCAPE,
+    /// Surface based CIN (J/kg).
+    SBCIN,
+    /// Mixed layer CAPE (J/kg).
+    MLCAPE,
+    /// Mixed layer CIN (J/kg).
+    MLCIN,
+    /// Most unstable CAPE (J/kg).
+    MUCAPE,
+    /// Height of the mixed layer parcel LCL (m AGL).
+    LCLHeight,
+    /// Height of the mixed layer parcel LFC (m AGL).
+    LFCHeight,
+    /// Height of the mixed layer parcel equilibrium level (m AGL).
+    ELHeight,
+    /// Precipitable water (mm).
+    PW,
+    /// Mean mixing ratio in the lowest 100 hPa (g/kg).
+    MixingRatio,
+    /// Low elevation Haines index.
+    HainesLow,
+    /// Mid elevation Haines index.
+    HainesMid,
+    /// High elevation Haines index.
+    HainesHigh,
+    /// 850-500 hPa temperature lapse rate (C/km, positive when cooling with height).
+    LapseRate850To500,
+    /// 700-500 hPa temperature lapse rate (C/km, positive when cooling with height).
+    LapseRate700To500,
+    /// Mixing height (m AGL).
+    MixingHeight,
+    /// Mean wind speed in the mixed layer (m/s).
+    TransportWindSpeed,
+    /// Mean wind direction in the mixed layer (degrees).
+    TransportWindDirection,
+    /// Ventilation index, mixing height times transport wind speed (m^2/s).
+    VentilationIndex,
+    /// Height of the highest freezing level below 500 hPa (m MSL).
+    FreezingLevel,
+    /// Height of the highest wet bulb zero level below 500 hPa (m MSL).
+    WetBulbZero,
+    /// Depth of the deepest above freezing layer aloft, zero if there is none (m).
+    WarmLayerDepth,
+    /// Maximum temperature in the deepest above freezing layer aloft (C).
+    WarmLayerMaxT,
+    /// Magnitude of the bulk wind shear from the surface to 1 km AGL (m/s).
+    BulkShear0To1km,
+    /// Magnitude of the bulk wind shear from the surface to 6 km AGL (m/s).
+    BulkShear0To6km,
+    /// Storm relative helicity from the surface to 1 km AGL for a Bunkers right mover (m^2/s^2).
+    SRH0To1km,
+    /// Storm relative helicity from the surface to 3 km AGL for a Bunkers right mover (m^2/s^2).
+    SRH0To3km,
+    /// Storm relative helicity in the effective inflow layer for a Bunkers right mover, zero if
+    /// there is no effective inflow layer (m^2/s^2).
+    EffectiveSRH,
+    /// Magnitude of the effective bulk wind shear, from the bottom of the effective inflow layer to
+    /// half the height of the most unstable parcel equilibrium level. Zero if there is no effective
+    /// inflow layer (m/s).
+    EffectiveBulkShear,
+    /// Significant tornado parameter, effective layer version with mixed layer CAPE, CIN and LCL.
+    STP,
+    /// Supercell composite parameter using most unstable CAPE and the effective layer shear and
+    /// helicity.
+    SCP,
+    /// A state variable at a mandatory pressure level.
+    Level {
+        element: LevelElement,
+        pressure: MandatoryLevel,
+    },
+    /// Surface (2 m) temperature (C).
+    SfcTemperature,
+    /// Surface (2 m) dew point (C).
+    SfcDewPoint,
+    /// Surface (2 m) relative humidity (%).
+    SfcRH,
+    /// Surface (10 m) wind speed (m/s). Bufkit files do not have wind gusts.
+    SfcWindSpeed,
+    /// Surface (10 m) wind direction (degrees).
+    SfcWindDirection,
+    /// Mean sea level pressure (hPa).
+    MSLP,
+    /// Model precipitation in the previous hour (mm).
+    Precip1Hr,
+    /// Model convective precipitation in the previous hour (mm).
+    ConvectivePrecip1Hr,
+    /// Model snowfall in the previous hour (kg/m^2).
+    SnowFall1Hr,
+    /// Model snow ratio.
+    SnowRatio,
+    /// Model skin temperature (C).
+    SkinTemperature,
+    /// Model visibility (km).
+    Visibility,
+    /// PFT assuming a fixed mean wind speed (m/s) in the mixed layer instead of the one in the
+    /// sounding. These are added with `ClimoElementRegistry::add_pft`.
+    FixedWindPFT {
+        name: &'static str,
+        mean_wind_mps: u32,
+    },
+    /// An element from a `ClimoElementCalc` added to a `ClimoElementRegistry`.
+    Custom(&'static dyn ClimoElementCalc),
+}
+
+impl ClimoElement {
+    /// The elements stored by versions of bufcli before the elements stored from each file were
+    /// recorded.
+    pub const LEGACY: &'static [ClimoElement] = &[
+        ClimoElement::HDW,
+        ClimoElement::BlowUpDt,
+        ClimoElement::PFT,
+        ClimoElement::DCAPE,
+    ];
+
+    /// All the built in elements.
+    pub const ALL: &'static [ClimoElement] = &[
+        ClimoElement::HDW,
+        ClimoElement::BlowUpDt,
+        ClimoElement::BlowUpHeight,
+        ClimoElement::BlowUpCloudDt,
+        ClimoElement::BlowUpMIB,
+        ClimoElement::BlowUpPctWet,
+        ClimoElement::PFT,
+        ClimoElement::DCAPE,
+        ClimoElement::SBCAPE,
+        ClimoElement::SBCIN,
+        ClimoElement::MLCAPE,
+        ClimoElement::MLCIN,
+        ClimoElement::MUCAPE,
+        ClimoElement::LCLHeight,
+        ClimoElement::LFCHeight,
+        ClimoElement::ELHeight,
+        ClimoElement::PW,
+        ClimoElement::MixingRatio,
+        ClimoElement::HainesLow,
+        ClimoElement::HainesMid,
+        ClimoElement::HainesHigh,
+        ClimoElement::LapseRate850To500,
+        ClimoElement::LapseRate700To500,
+        ClimoElement::MixingHeight,
+        ClimoElement::TransportWindSpeed,
+        ClimoElement::TransportWindDirection,
+        ClimoElement::VentilationIndex,
+        ClimoElement::FreezingLevel,
+        ClimoElement::WetBulbZero,
+        ClimoElement::WarmLayerDepth,
+        ClimoElement::WarmLayerMaxT,
+        ClimoElement::BulkShear0To1km,
+        ClimoElement::BulkShear0To6km,
+        ClimoElement::SRH0To1km,
+        ClimoElement::SRH0To3km,
+        ClimoElement::EffectiveSRH,
+        ClimoElement::EffectiveBulkShear,
+        ClimoElement::STP,
+        ClimoElement::SCP,
+        ClimoElement::Level {
+            element: LevelElement::Temperature,
+            pressure: MandatoryLevel::P850,
+        },
+        ClimoElement::Level {
+            element: LevelElement::DewPoint,
+            pressure: MandatoryLevel::P850,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindSpeed,
+            pressure: MandatoryLevel::P850,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindDirection,
+            pressure: MandatoryLevel::P850,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Height,
+            pressure: MandatoryLevel::P850,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Temperature,
+            pressure: MandatoryLevel::P700,
+        },
+        ClimoElement::Level {
+            element: LevelElement::DewPoint,
+            pressure: MandatoryLevel::P700,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindSpeed,
+            pressure: MandatoryLevel::P700,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindDirection,
+            pressure: MandatoryLevel::P700,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Height,
+            pressure: MandatoryLevel::P700,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Temperature,
+            pressure: MandatoryLevel::P500,
+        },
+        ClimoElement::Level {
+            element: LevelElement::DewPoint,
+            pressure: MandatoryLevel::P500,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindSpeed,
+            pressure: MandatoryLevel::P500,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindDirection,
+            pressure: MandatoryLevel::P500,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Height,
+            pressure: MandatoryLevel::P500,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Temperature,
+            pressure: MandatoryLevel::P300,
+        },
+        ClimoElement::Level {
+            element: LevelElement::DewPoint,
+            pressure: MandatoryLevel::P300,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindSpeed,
+            pressure: MandatoryLevel::P300,
+        },
+        ClimoElement::Level {
+            element: LevelElement::WindDirection,
+            pressure: MandatoryLevel::P300,
+        },
+        ClimoElement::Level {
+            element: LevelElement::Height,
+            pressure: MandatoryLevel::P300,
+        },
+        ClimoElement::SfcTemperature,
+        ClimoElement::SfcDewPoint,
+        ClimoElement::SfcRH,
+        ClimoElement::SfcWindSpeed,
+        ClimoElement::SfcWindDirection,
+        ClimoElement::MSLP,
+        ClimoElement::Precip1Hr,
+        ClimoElement::ConvectivePrecip1Hr,
+        ClimoElement::SnowFall1Hr,
+        ClimoElement::SnowRatio,
+        ClimoElement::SkinTemperature,
+        ClimoElement::Visibility,
+    ];
+
+    /// Get a short, lowercase name for this element, suitable for use on the command line.
+    pub fn as_static_str(self) -> &'static str {
+        use ClimoElement::*;
+
+        match self {
+            HDW => "hdw",
+            BlowUpDt => "blow_up_dt",
+            BlowUpHeight => "blow_up_height",
+            BlowUpCloudDt => "blow_up_cloud_dt",
+            BlowUpMIB => "blow_up_mib",
+            BlowUpPctWet => "blow_up_pct_wet",
+            PFT => "pft",
+            DCAPE => "dcape",
+            SBCAPE => "sbcape",
+            SBCIN => "sbcin",
+            MLCAPE => "mlcape",
+            MLCIN => "mlcin",
+            MUCAPE => "mucape",
+            LCLHeight => "lcl_hgt",
+            LFCHeight => "lfc_hgt",
+            ELHeight => "el_hgt",
+            PW => "pw",
+            MixingRatio => "mixing_ratio",
+            HainesLow => "haines_low",
+            HainesMid => "haines_mid",
+            HainesHigh => "haines_high",
+            LapseRate850To500 => "lapse_rate_850_500",
+            LapseRate700To500 => "lapse_rate_700_500",
+            MixingHeight => "mixing_hgt",
+            TransportWindSpeed => "transport_wind_spd",
+            TransportWindDirection => "transport_wind_dir",
+            VentilationIndex => "vent_index",
+            FreezingLevel => "frz_lvl",
+            WetBulbZero => "wbz_lvl",
+            WarmLayerDepth => "warm_layer_depth",
+            WarmLayerMaxT => "warm_layer_max_t",
+            BulkShear0To1km => "shear_0_1km",
+            BulkShear0To6km => "shear_0_6km",
+            SRH0To1km => "srh_0_1km",
+            SRH0To3km => "srh_0_3km",
+            EffectiveSRH => "eff_srh",
+            EffectiveBulkShear => "eff_shear",
+            STP => "stp",
+            SCP => "scp",
+            Level { element, pressure } => LEVEL_NAMES[element as usize][pressure as usize],
+            SfcTemperature => "sfc_t",
+            SfcDewPoint => "sfc_td",
+            SfcRH => "sfc_rh",
+            SfcWindSpeed => "sfc_wspd",
+            SfcWindDirection => "sfc_wdir",
+            MSLP => "mslp",
+            Precip1Hr => "precip_1hr",
+            ConvectivePrecip1Hr => "conv_precip_1hr",
+            SnowFall1Hr => "snow_1hr",
+            SnowRatio => "snow_ratio",
+            SkinTemperature => "skin_t",
+            Visibility => "visibility",
+            FixedWindPFT { name, .. } => name,
+            Custom(calc) => calc.name(),
+        }
+    }
+
+    /// The name of the column in the `cli` table that stores this element. This is the same as
+    /// `as_static_str` except for a few columns that predate the short names.
+    pub fn column_name(self) -> &'static str {
+        use ClimoElement::*;
+
+        match self {
+            BlowUpDt => "el_blow_up_dt",
+            BlowUpHeight => "el_blow_up_dz",
+            BlowUpCloudDt => "cloud_blow_up_dt",
+            _ => self.as_static_str(),
+        }
+    }
+
+    /// The SQL type of the column that stores this element.
+    pub fn sql_type(self) -> &'static str {
+        use ClimoElement::*;
+
+        match self {
+            HDW
+            | PFT
+            | DCAPE
+            | SBCAPE
+            | SBCIN
+            | MLCAPE
+            | MLCIN
+            | MUCAPE
+            | LCLHeight
+            | LFCHeight
+            | ELHeight
+            | HainesLow
+            | HainesMid
+            | HainesHigh
+            | MixingHeight
+            | TransportWindDirection
+            | VentilationIndex
+            | FreezingLevel
+            | WetBulbZero
+            | WarmLayerDepth
+            | SRH0To1km
+            | SRH0To3km
+            | EffectiveSRH
+            | SfcWindDirection
+            | FixedWindPFT { .. } => "INT",
+            Level {
+                element: LevelElement::WindDirection,
+                ..
+            }
+            | Level {
+                element: LevelElement::Height,
+                ..
+            } => "INT",
+            Custom(calc) => calc.sql_type(),
+            _ => "REAL",
+        }
+    }
+
+    /// Whether this element is a direction in degrees, which can't be averaged or ranked like the
+    /// other elements.
+    pub fn is_direction(self) -> bool {
+        use ClimoElement::*;
+
+        matches!(
+            self,
+            TransportWindDirection
+                | SfcWindDirection
+                | Level {
+                    element: LevelElement::WindDirection,
+                    ..
+                }
+        )
+    }
+
+    /// Get the built in element stored in a column of the `cli` table. Use
+    /// `ClimoElementRegistry::find_column` to include registered elements.
+    pub fn from_column_name(col_name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|elem| elem.column_name() == col_name)
+    }
+}
+
+/// The names of the level elements, which are also their column names, indexed by
+/// `[LevelElement][MandatoryLevel]`.
+const LEVEL_NAMES: [[&str; 4]; 5] = [
+    ["t_850", "t_700", "t_500", "t_300"],
+    ["td_850", "td_700", "td_500", "td_300"],
+    ["wspd_850", "wspd_700", "wspd_500", "wspd_300"],
+    ["wdir_850", "wdir_700", "wdir_500", "wdir_300"],
+    ["hgt_850", "hgt_700", "hgt_500", "hgt_300"],
+];
+
+impl FromStr for ClimoElement {
+    type Err = BufcliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|elem| elem.as_static_str() == s)
+            .ok_or(BufcliError::new("Unknown element"))
+    }
+}
+
+impl std::fmt::Display for ClimoElement {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.pad(self.as_static_str())
+    }
 }
 
+/// State variables stored at each mandatory pressure level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LevelElement {
+    /// Temperature (C).
+    Temperature,
+    /// Dew point (C).
+    DewPoint,
+    /// Wind speed (m/s).
+    WindSpeed,
+    /// Wind direction (degrees).
+    WindDirection,
+    /// Geopotential height (m).
+    Height,
+}
+
+/// The mandatory pressure levels that level elements are stored at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MandatoryLevel {
+    P850,
+    P700,
+    P500,
+    P300,
+}
+
+impl MandatoryLevel {
+    /// All the levels, from the bottom up.
+    pub const ALL: [MandatoryLevel; 4] = [
+        MandatoryLevel::P850,
+        MandatoryLevel::P700,
+        MandatoryLevel::P500,
+        MandatoryLevel::P300,
+    ];
+
+    /// Get the pressure of this level in hPa.
+    pub fn hpa(self) -> f64 {
+        use MandatoryLevel::*;
+
+        match self {
+            P850 => 850.0,
+            P700 => 700.0,
+            P500 => 500.0,
+            P300 => 300.0,
+        }
+    }
+}
+
+/// The outcome of processing a file from the archive, as recorded in the `processed_files` table.
+///
+/// They are ordered from best to worst, so the worst of several outcomes for a file is the
+/// largest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FileStatus {
+    /// The file was parsed and its data stored.
+    Ok,
+    /// The file was parsed, but some or all of its data could not be used.
+    Skipped,
+    /// The file could not be read or parsed.
+    ParseError,
+}
+
+impl FileStatus {
+    /// All the statuses.
+    pub const ALL: [FileStatus; 3] = [FileStatus::Ok, FileStatus::Skipped, FileStatus::ParseError];
+
+    pub fn as_static_str(self) -> &'static str {
+        match self {
+            FileStatus::Ok => "ok",
+            FileStatus::Skipped => "skipped",
+            FileStatus::ParseError => "parse_error",
+        }
+    }
+}
+
+impl FromStr for FileStatus {
+    type Err = BufcliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|status| status.as_static_str() == s)
+            .ok_or(BufcliError::new("Unknown file status"))
+    }
+}
+
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.pad(self.as_static_str())
+    }
+}
+
+/// Hash the contents of a file so changes to it can be noticed.
+///
+/// This is the 64 bit FNV-1a hash as hex, which is stable across platforms and versions of Rust.
+pub fn content_hash(data: &str) -> String {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let hash = data.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    });
+
+    format!("{:016x}", hash)
+}
+
+/// Quote an identifier, like a column name, for use in generated SQL.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+mod migrate;
+
 mod populate;
-pub use populate::ClimoPopulateInterface;
+pub use populate::{ClimoPopulateInterface, ProcessedFile};
+
+mod query;
+pub use query::{BadFiles, ClimoQueryFilter, ClimoQueryInterface, ForecastPairs, ModelPairs};
+
+mod registry;
+pub use registry::{ClimoElementCalc, ClimoElementRegistry};
 
 mod stats_record;
 pub use stats_record::StatsRecord;
@@ -0,0 +1,3 @@
+INSERT INTO element_params (column_name, param, value)
+VALUES (?1, ?2, ?3)
+ON CONFLICT (column_name, param) DO UPDATE SET value = excluded.value
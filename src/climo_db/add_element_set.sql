@@ -0,0 +1,3 @@
+INSERT INTO element_sets (elements) VALUES (?1)
+ON CONFLICT (elements) DO UPDATE SET elements = excluded.elements
+RETURNING id
@@ -0,0 +1,3 @@
+INSERT OR REPLACE INTO processed_files (
+    station_num, model, init_time, status, hash, reason, element_set, lead_times)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
@@ -0,0 +1,4 @@
+SELECT init_time, status, reason
+FROM processed_files
+WHERE station_num = ?1 AND model = ?2 AND status != 'ok'
+ORDER BY init_time ASC
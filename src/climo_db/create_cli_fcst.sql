@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS cli_fcst (
+    station_num   INT  NOT NULL,
+    model         TEXT NOT NULL,
+
+    init_time     TEXT NOT NULL,
+    lead_time     INT  NOT NULL,
+
+    valid_time    TEXT NOT NULL,
+    year_lcl      INT  NOT NULL,
+    month_lcl     INT  NOT NULL,
+    day_lcl       INT  NOT NULL,
+    hour_lcl      INT  NOT NULL,
+
+    PRIMARY KEY (station_num, model, init_time, lead_time));
+
+CREATE INDEX IF NOT EXISTS cli_fcst_lead_idx ON cli_fcst (station_num, model, lead_time);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS element_params (
+    column_name TEXT NOT NULL,
+    param       TEXT NOT NULL,
+    value       REAL NOT NULL,
+    PRIMARY KEY (column_name, param));
+
+INSERT OR IGNORE INTO element_params (column_name, param, value) VALUES ('pft', 'moisture_ratio', 15.0);
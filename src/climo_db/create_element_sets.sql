@@ -0,0 +1,3 @@
+CREATE TABLE IF NOT EXISTS element_sets (
+    id       INTEGER PRIMARY KEY,
+    elements TEXT NOT NULL UNIQUE);
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS processed_files (
+    station_num INT  NOT NULL,
+    model       TEXT NOT NULL,
+    init_time   TEXT NOT NULL,
+
+    status      TEXT NOT NULL,
+    hash        TEXT,
+
+    PRIMARY KEY (station_num, model, init_time));
@@ -0,0 +1 @@
+SELECT param, value FROM element_params WHERE column_name = ?1 ORDER BY param
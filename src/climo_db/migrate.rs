@@ -0,0 +1,441 @@
+use super::{quote_identifier, ClimoElement};
+use crate::BufcliError;
+use rusqlite::Connection;
+use std::error::Error;
+
+/// A change to the schema of the climo database.
+enum Migration {
+    /// Add nullable columns to a table, skipping any that already exist.
+    AddColumns {
+        table: &'static str,
+        columns: &'static [(&'static str, &'static str)],
+    },
+    /// Run a script, e.g. to create a new table. It must be safe to run more than once.
+    Sql(&'static str),
+    /// Add any columns of one table that another is missing.
+    CopyColumns {
+        from: &'static str,
+        to: &'static str,
+    },
+}
+
+/// The tables that have a column for every element.
+pub(super) const ELEMENT_TABLES: [&str; 2] = ["cli", "cli_fcst"];
+
+/// All the migrations in the order they must be applied.
+///
+/// The schema version stored in the database with `PRAGMA user_version` is the number of these
+/// that have been applied, and version 0 is the schema in `create_climate_data_db.sql`. Only ever
+/// add to the end of this list.
+///
+/// Element columns are not added here anymore, `add_element_columns` adds any that are missing
+/// each time the database is opened.
+const MIGRATIONS: &[Migration] = &[
+    // 1: The rest of the blow up analysis.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("el_blow_up_dz", "REAL"),
+            ("cloud_blow_up_dt", "REAL"),
+            ("blow_up_mib", "REAL"),
+            ("blow_up_pct_wet", "REAL"),
+        ],
+    },
+    // 2: Convective instability.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("sbcape", "INT"),
+            ("sbcin", "INT"),
+            ("mlcape", "INT"),
+            ("mlcin", "INT"),
+            ("mucape", "INT"),
+            ("lcl_hgt", "INT"),
+            ("lfc_hgt", "INT"),
+            ("el_hgt", "INT"),
+        ],
+    },
+    // 3: Moisture.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[("pw", "REAL"), ("mixing_ratio", "REAL")],
+    },
+    // 4: Fire weather stability.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("haines_low", "INT"),
+            ("haines_mid", "INT"),
+            ("haines_high", "INT"),
+            ("lapse_rate_850_500", "REAL"),
+            ("lapse_rate_700_500", "REAL"),
+        ],
+    },
+    // 5: Smoke dispersion.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("mixing_hgt", "INT"),
+            ("transport_wind_spd", "REAL"),
+            ("transport_wind_dir", "INT"),
+            ("vent_index", "INT"),
+        ],
+    },
+    // 6: Winter weather.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("frz_lvl", "INT"),
+            ("wbz_lvl", "INT"),
+            ("warm_layer_depth", "INT"),
+            ("warm_layer_max_t", "REAL"),
+        ],
+    },
+    // 7: Kinematics.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("shear_0_1km", "REAL"),
+            ("shear_0_6km", "REAL"),
+            ("srh_0_1km", "INT"),
+            ("srh_0_3km", "INT"),
+        ],
+    },
+    // 8: Severe weather composites and their effective layer ingredients.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("eff_srh", "INT"),
+            ("eff_shear", "REAL"),
+            ("stp", "REAL"),
+            ("scp", "REAL"),
+        ],
+    },
+    // 9: Mandatory levels.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("t_850", "REAL"),
+            ("td_850", "REAL"),
+            ("wspd_850", "REAL"),
+            ("wdir_850", "INT"),
+            ("hgt_850", "INT"),
+            ("t_700", "REAL"),
+            ("td_700", "REAL"),
+            ("wspd_700", "REAL"),
+            ("wdir_700", "INT"),
+            ("hgt_700", "INT"),
+            ("t_500", "REAL"),
+            ("td_500", "REAL"),
+            ("wspd_500", "REAL"),
+            ("wdir_500", "INT"),
+            ("hgt_500", "INT"),
+            ("t_300", "REAL"),
+            ("td_300", "REAL"),
+            ("wspd_300", "REAL"),
+            ("wdir_300", "INT"),
+            ("hgt_300", "INT"),
+        ],
+    },
+    // 10: Surface.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("sfc_t", "REAL"),
+            ("sfc_td", "REAL"),
+            ("sfc_rh", "REAL"),
+            ("sfc_wspd", "REAL"),
+            ("sfc_wdir", "INT"),
+            ("mslp", "REAL"),
+        ],
+    },
+    // 11: Bufkit surface data.
+    Migration::AddColumns {
+        table: "cli",
+        columns: &[
+            ("precip_1hr", "REAL"),
+            ("conv_precip_1hr", "REAL"),
+            ("snow_1hr", "REAL"),
+            ("snow_ratio", "REAL"),
+            ("skin_t", "REAL"),
+            ("visibility", "REAL"),
+        ],
+    },
+    // 12: Parameters used to calculate elements.
+    Migration::Sql(include_str!("create_element_params.sql")),
+    // 13: Forecasts at chosen lead times.
+    Migration::Sql(include_str!("create_cli_fcst.sql")),
+    // 14: Element columns for the forecasts.
+    Migration::CopyColumns {
+        from: "cli",
+        to: "cli_fcst",
+    },
+    // 15: The files that have been processed and how it went.
+    Migration::Sql(include_str!("create_processed_files.sql")),
+    // 16: Why files could not be processed.
+    Migration::AddColumns {
+        table: "processed_files",
+        columns: &[("reason", "TEXT")],
+    },
+    // 17: The sets of elements stored from processed files.
+    Migration::Sql(include_str!("create_element_sets.sql")),
+    // 18: What was stored from processed files.
+    Migration::AddColumns {
+        table: "processed_files",
+        columns: &[("element_set", "INT"), ("lead_times", "TEXT")],
+    },
+];
+
+/// The schema version of a fully migrated database.
+const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+/// Bring the schema of the database up to date, keeping any data already in it.
+pub(super) fn migrate(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    if schema_version(conn)? == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    // Several threads may connect at once, so the version is checked again once this one holds
+    // the write lock, and only one of them does the migration.
+    write_locked(conn, apply_all)
+}
+
+/// Add a column for each of `elements` to the `ELEMENT_TABLES` that don't have one yet.
+pub(super) fn add_element_columns(
+    conn: &Connection,
+    elements: &[ClimoElement],
+) -> Result<(), Box<dyn Error>> {
+    let columns: Vec<(&str, &str)> = elements
+        .iter()
+        .map(|elem| (elem.column_name(), elem.sql_type()))
+        .collect();
+
+    let mut missing = false;
+    for table in ELEMENT_TABLES.iter() {
+        let existing = column_names(conn, table)?;
+        missing |= columns
+            .iter()
+            .any(|(name, _)| !existing.iter().any(|col| col == name));
+    }
+    if !missing {
+        return Ok(());
+    }
+
+    // As with the migrations, another connection may have added them by the time this one holds
+    // the write lock, and add_columns checks again.
+    write_locked(conn, |conn| {
+        for table in ELEMENT_TABLES.iter() {
+            add_columns(conn, table, &columns)?;
+        }
+        Ok(())
+    })
+}
+
+/// Run `f` in a transaction that holds the write lock on the database from the start, rolling it
+/// back if `f` fails.
+fn write_locked<F>(conn: &Connection, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&Connection) -> Result<(), Box<dyn Error>>,
+{
+    conn.execute_batch("BEGIN IMMEDIATE TRANSACTION")?;
+
+    match f(conn) {
+        Ok(()) => conn.execute_batch("COMMIT TRANSACTION")?,
+        Err(err) => {
+            conn.execute_batch("ROLLBACK TRANSACTION")?;
+            return Err(err);
+        }
+    }
+
+    Ok(())
+}
+
+fn apply_all(conn: &Connection) -> Result<(), Box<dyn Error>> {
+    let version = schema_version(conn)?;
+
+    if version > SCHEMA_VERSION {
+        return Err(BufcliError::new("climo database is newer than this version of bufcli").into());
+    }
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        apply(conn, migration)?;
+        conn.pragma_update(None, "user_version", idx as i64 + 1)?;
+    }
+
+    Ok(())
+}
+
+/// Get the schema version of the database.
+pub(super) fn schema_version(conn: &Connection) -> Result<i64, Box<dyn Error>> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+fn apply(conn: &Connection, migration: &Migration) -> Result<(), Box<dyn Error>> {
+    match migration {
+        Migration::AddColumns { table, columns } => add_columns(conn, table, columns),
+        Migration::Sql(sql) => Ok(conn.execute_batch(sql)?),
+        Migration::CopyColumns { from, to } => {
+            let columns = column_names_and_types(conn, from)?;
+            let columns: Vec<(&str, &str)> = columns
+                .iter()
+                .map(|(name, sql_type)| (name.as_str(), sql_type.as_str()))
+                .collect();
+            add_columns(conn, to, &columns)
+        }
+    }
+}
+
+/// Add nullable columns to a table, skipping any that already exist.
+pub(super) fn add_columns(
+    conn: &Connection,
+    table: &str,
+    columns: &[(&str, &str)],
+) -> Result<(), Box<dyn Error>> {
+    let existing = column_names(conn, table)?;
+
+    for (name, sql_type) in columns.iter() {
+        if !existing.iter().any(|col| col == name) {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {} ADD COLUMN {} {}",
+                table,
+                quote_identifier(name),
+                sql_type
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the names of the columns in a table.
+fn column_names(conn: &Connection, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
+    let columns = column_names_and_types(conn, table)?;
+
+    Ok(columns.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Get the names and SQL types of the columns in a table.
+fn column_names_and_types(
+    conn: &Connection,
+    table: &str,
+) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let columns: Result<Vec<(String, String)>, _> = stmt
+        .query_map([], |row| Ok((row.get(1)?, row.get(2)?)))?
+        .collect();
+
+    Ok(columns?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database with the original schema, version 0, and one row of data.
+    fn version_0() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(include_str!("create_climate_data_db.sql"))
+            .unwrap();
+        conn.execute_batch(concat!(
+            "INSERT INTO cli (station_num, model, valid_time, year_lcl, month_lcl, day_lcl,",
+            " hour_lcl, hdw, el_blow_up_dt, pft, dcape)",
+            " VALUES (1, 'gfs', '2020-01-01 00:00:00', 2019, 12, 31, 17, 42, 3.5, 100, 250)"
+        ))
+        .unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn test_migrate_from_version_0() {
+        let conn = version_0();
+        assert_eq!(schema_version(&conn).unwrap(), 0);
+
+        migrate(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+
+        let cli = column_names(&conn, "cli").unwrap();
+        for col in [
+            "el_blow_up_dz",
+            "cloud_blow_up_dt",
+            "blow_up_mib",
+            "sbcape",
+            "vent_index",
+        ] {
+            assert!(cli.iter().any(|c| c == col), "missing {}", col);
+        }
+        let files = column_names(&conn, "processed_files").unwrap();
+        for col in ["hash", "element_set", "lead_times"] {
+            assert!(files.iter().any(|c| c == col), "missing {}", col);
+        }
+
+        let row: (i64, f64, i64, i64) = conn
+            .query_row(
+                "SELECT hdw, el_blow_up_dt, pft, dcape FROM cli",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .unwrap();
+        assert_eq!(row, (42, 3.5, 100, 250));
+    }
+
+    #[test]
+    fn test_migrate_twice() {
+        let conn = version_0();
+        migrate(&conn).unwrap();
+        let cli = column_names(&conn, "cli").unwrap();
+
+        // Running every migration again must not fail either, e.g. if the version was lost.
+        conn.pragma_update(None, "user_version", 0).unwrap();
+        migrate(&conn).unwrap();
+
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert_eq!(column_names(&conn, "cli").unwrap(), cli);
+    }
+
+    #[test]
+    fn test_newer_version_is_an_error() {
+        let conn = version_0();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+
+        assert!(migrate(&conn).is_err());
+    }
+
+    #[test]
+    fn test_add_element_columns() {
+        let conn = version_0();
+        migrate(&conn).unwrap();
+        add_element_columns(&conn, ClimoElement::ALL).unwrap();
+
+        for table in ELEMENT_TABLES.iter() {
+            let columns = column_names_and_types(&conn, table).unwrap();
+            for elem in ClimoElement::ALL.iter() {
+                assert!(
+                    columns
+                        .iter()
+                        .any(|(name, sql_type)| name == elem.column_name()
+                            && sql_type == elem.sql_type()),
+                    "missing {} in {}",
+                    elem,
+                    table
+                );
+            }
+        }
+
+        // Nothing is added twice.
+        let before = column_names(&conn, "cli").unwrap();
+        add_element_columns(&conn, ClimoElement::ALL).unwrap();
+        assert_eq!(column_names(&conn, "cli").unwrap(), before);
+    }
+
+    #[test]
+    fn test_add_columns_quotes_names() {
+        let conn = version_0();
+        add_columns(&conn, "cli", &[("order", "REAL"), ("a \"b\"", "INT")]).unwrap();
+
+        let cli = column_names(&conn, "cli").unwrap();
+        assert!(cli.iter().any(|c| c == "order"));
+        assert!(cli.iter().any(|c| c == "a \"b\""));
+    }
+}
@@ -1,16 +1,35 @@
 use super::ClimoDB;
+use super::FileStatus;
 use super::StatsRecord;
+use super::{quote_identifier, ClimoElement};
 use bufkit_data::{Model, SiteInfo};
-use chrono::{Datelike, FixedOffset, NaiveDateTime, TimeZone, Timelike};
+use chrono::{Datelike, Duration, FixedOffset, NaiveDateTime, TimeZone, Timelike};
 use rusqlite::{types::ToSql, Statement};
-use std::error::Error;
+use std::{collections::HashMap, error::Error};
+
+/// A file that has been processed, as recorded in the `processed_files` table.
+#[derive(Clone, Debug)]
+pub struct ProcessedFile {
+    pub init_time: NaiveDateTime,
+    pub status: FileStatus,
+    /// The hash of the contents of the file when it was processed, if it could be read.
+    pub hash: Option<String>,
+    /// The columns of the elements stored from the file. Files processed before these were
+    /// recorded have the `ClimoElement::LEGACY` ones.
+    pub elements: Vec<String>,
+    /// The forecast lead times stored from the file.
+    pub lead_times: Vec<u32>,
+}
 
 /// The struct creates and caches several prepared statements for adding data to the climo database.
 pub struct ClimoPopulateInterface<'a, 'b: 'a> {
     climo_db: &'b ClimoDB,
     add_location_query: Statement<'a>,
-    add_data_query: Statement<'a>,
-    init_times_query: Statement<'a>,
+    add_processed_file_query: Statement<'a>,
+    valid_times_query: Statement<'a>,
+    processed_files_query: Statement<'a>,
+    add_element_set_query: Statement<'a>,
+    element_set_ids: HashMap<String, i64>,
     write_buffer: Vec<StatsRecord>,
 }
 
@@ -20,18 +39,24 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
     pub fn initialize(climo_db: &'b ClimoDB) -> Result<Self, Box<dyn Error>> {
         let conn = &climo_db.conn;
         let add_location_query = conn.prepare(include_str!("add_location.sql"))?;
-        let add_data_query = conn.prepare(include_str!("add_data.sql"))?;
-        let init_times_query = conn.prepare(include_str!("init_times.sql"))?;
+        let add_processed_file_query = conn.prepare(include_str!("add_processed_file.sql"))?;
+        let valid_times_query = conn.prepare(include_str!("valid_times.sql"))?;
+        let processed_files_query = conn.prepare(include_str!("processed_files.sql"))?;
+        let add_element_set_query = conn.prepare(include_str!("add_element_set.sql"))?;
 
         Ok(ClimoPopulateInterface {
             climo_db,
             add_location_query,
-            add_data_query,
-            init_times_query,
+            add_processed_file_query,
+            valid_times_query,
+            processed_files_query,
+            add_element_set_query,
+            element_set_ids: HashMap::new(),
             write_buffer: Vec::with_capacity(ClimoPopulateInterface::BUFSIZE),
         })
     }
 
+    /// Get the valid times with analyses in the database for a site and model.
     #[inline]
     pub fn valid_times_for(
         &mut self,
@@ -42,14 +67,70 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
         let station_num: u32 = site.station_num.into();
 
         let valid_times: Result<Vec<NaiveDateTime>, _> = self
-            .init_times_query
-            .query_map(&[&station_num as &dyn ToSql, &model_str], |row| row.get(0))?
+            .valid_times_query
+            .query_map([&station_num as &dyn ToSql, &model_str], |row| row.get(0))?
             .collect();
         let valid_times = valid_times?;
 
         Ok(valid_times)
     }
 
+    /// Get the files that have been processed for a site and model.
+    pub fn processed_files_for(
+        &mut self,
+        site: &SiteInfo,
+        model: Model,
+    ) -> Result<Vec<ProcessedFile>, Box<dyn Error>> {
+        let model_str = model.as_static_str();
+        let station_num: u32 = site.station_num.into();
+
+        type Row = (
+            NaiveDateTime,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        );
+        let rows: Result<Vec<Row>, _> = self
+            .processed_files_query
+            .query_map([&station_num as &dyn ToSql, &model_str], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })?
+            .collect();
+
+        rows?
+            .into_iter()
+            .map(|(init_time, status, hash, elements, lead_times)| {
+                let lead_times = match lead_times {
+                    Some(lead_times) => split_list(&lead_times)
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()?,
+                    None => vec![],
+                };
+
+                Ok(ProcessedFile {
+                    init_time,
+                    status: status.parse()?,
+                    hash,
+                    elements: match elements {
+                        Some(elements) => split_list(&elements).map(ToOwned::to_owned).collect(),
+                        None => ClimoElement::LEGACY
+                            .iter()
+                            .map(|elem| elem.column_name().to_owned())
+                            .collect(),
+                    },
+                    lead_times,
+                })
+            })
+            .collect()
+    }
+
     #[inline]
     pub fn add(&mut self, record: StatsRecord) -> Result<(), Box<dyn Error>> {
         debug_assert!(self.write_buffer.len() <= ClimoPopulateInterface::BUFSIZE);
@@ -62,6 +143,16 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
         Ok(())
     }
 
+    /// Write any buffered records to the database and compact it.
+    ///
+    /// Records still in the buffer are lost if this is not called.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        self.climo_db.conn.execute("VACUUM", [])?;
+
+        Ok(())
+    }
+
     #[inline]
     fn flush(&mut self) -> Result<(), Box<dyn Error>> {
         use self::StatsRecord::*;
@@ -75,36 +166,72 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
                         site,
                         model,
                         valid_time,
-                        hdw,
-                        blow_up_dt,
-                        pft,
-                        dcape,
+                        values,
                     } => {
-                        let lcl_time = site
-                            .time_zone
-                            .unwrap_or_else(|| FixedOffset::west(0))
-                            .from_utc_datetime(&valid_time);
-                        let year_lcl = lcl_time.year();
-                        let month_lcl = lcl_time.month();
-                        let day_lcl = lcl_time.day();
-                        let hour_lcl = lcl_time.hour();
+                        let (year_lcl, month_lcl, day_lcl, hour_lcl) =
+                            local_time(&site, valid_time);
+                        let station_num: u32 = site.station_num.into();
+                        let model_str = model.as_static_str();
 
+                        let mut params: Vec<&dyn ToSql> = vec![
+                            &station_num,
+                            &model_str,
+                            &valid_time,
+                            &year_lcl,
+                            &month_lcl,
+                            &day_lcl,
+                            &hour_lcl,
+                        ];
+                        params.extend(values.iter().map(|(_, val)| val as &dyn ToSql));
+
+                        let query = add_data_sql(
+                            "cli",
+                            &CLI_KEY_COLUMNS,
+                            &CLI_PRIMARY_KEY,
+                            values.iter().map(|(elem, _)| *elem),
+                        );
+                        self.climo_db
+                            .conn
+                            .prepare_cached(&query)
+                            .and_then(|mut stmt| stmt.execute(params.as_slice()))
+                            .map(|_| ())
+                    }
+                    FcstData {
+                        site,
+                        model,
+                        init_time,
+                        lead_time,
+                        values,
+                    } => {
+                        let valid_time = init_time + Duration::hours(i64::from(lead_time));
+                        let (year_lcl, month_lcl, day_lcl, hour_lcl) =
+                            local_time(&site, valid_time);
                         let station_num: u32 = site.station_num.into();
+                        let model_str = model.as_static_str();
 
-                        self.add_data_query
-                            .execute(&[
-                                &station_num as &dyn ToSql,
-                                &model.as_static_str(),
-                                &valid_time,
-                                &year_lcl,
-                                &month_lcl,
-                                &day_lcl,
-                                &hour_lcl,
-                                &hdw,
-                                &blow_up_dt,
-                                &pft,
-                                &dcape,
-                            ])
+                        let mut params: Vec<&dyn ToSql> = vec![
+                            &station_num,
+                            &model_str,
+                            &init_time,
+                            &lead_time,
+                            &valid_time,
+                            &year_lcl,
+                            &month_lcl,
+                            &day_lcl,
+                            &hour_lcl,
+                        ];
+                        params.extend(values.iter().map(|(_, val)| val as &dyn ToSql));
+
+                        let query = add_data_sql(
+                            "cli_fcst",
+                            &FCST_KEY_COLUMNS,
+                            &FCST_PRIMARY_KEY,
+                            values.iter().map(|(elem, _)| *elem),
+                        );
+                        self.climo_db
+                            .conn
+                            .prepare_cached(&query)
+                            .and_then(|mut stmt| stmt.execute(params.as_slice()))
                             .map(|_| ())
                     }
                     Location {
@@ -117,7 +244,7 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
                         // unwrap should be ok because we filtered out sites without a name
                         let name: String = site.name.unwrap();
                         self.add_location_query
-                            .execute(&[
+                            .execute([
                                 &Into::<u32>::into(site.station_num) as &dyn ToSql,
                                 &name,
                                 &model.as_static_str(),
@@ -127,6 +254,49 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
                             ])
                             .map(|_| ())
                     }
+                    File {
+                        site,
+                        model,
+                        init_time,
+                        status,
+                        hash,
+                        reason,
+                        elements,
+                        lead_times,
+                    } => {
+                        let element_set = element_set_id(
+                            &mut self.add_element_set_query,
+                            &mut self.element_set_ids,
+                            &elements,
+                        );
+
+                        let mut lead_times = lead_times;
+                        lead_times.sort_unstable();
+                        lead_times.dedup();
+                        let lead_times: Option<String> = if lead_times.is_empty() {
+                            None
+                        } else {
+                            let lead_times: Vec<String> =
+                                lead_times.iter().map(ToString::to_string).collect();
+                            Some(lead_times.join(","))
+                        };
+
+                        let add_processed_file_query = &mut self.add_processed_file_query;
+                        element_set.and_then(|element_set| {
+                            add_processed_file_query
+                                .execute([
+                                    &Into::<u32>::into(site.station_num) as &dyn ToSql,
+                                    &model.as_static_str(),
+                                    &init_time,
+                                    &status.as_static_str(),
+                                    &hash,
+                                    &reason,
+                                    &element_set,
+                                    &lead_times,
+                                ])
+                                .map(|_| ())
+                        })
+                    }
                 }
             } {
                 eprintln!("Error adding data to database: {}", err);
@@ -141,9 +311,328 @@ impl<'a, 'b> ClimoPopulateInterface<'a, 'b> {
     }
 }
 
-impl<'a, 'b> Drop for ClimoPopulateInterface<'a, 'b> {
-    fn drop(&mut self) {
-        self.flush().unwrap();
-        self.climo_db.conn.execute("VACUUM", []).unwrap();
+/// The columns that identify a row of the `cli` table, in the order they are bound.
+pub(super) const CLI_KEY_COLUMNS: [&str; 7] = [
+    "station_num",
+    "model",
+    "valid_time",
+    "year_lcl",
+    "month_lcl",
+    "day_lcl",
+    "hour_lcl",
+];
+
+/// The primary key of the `cli` table.
+const CLI_PRIMARY_KEY: [&str; 7] = [
+    "station_num",
+    "valid_time",
+    "model",
+    "year_lcl",
+    "month_lcl",
+    "day_lcl",
+    "hour_lcl",
+];
+
+/// The columns that identify a row of the `cli_fcst` table, in the order they are bound.
+pub(super) const FCST_KEY_COLUMNS: [&str; 9] = [
+    "station_num",
+    "model",
+    "init_time",
+    "lead_time",
+    "valid_time",
+    "year_lcl",
+    "month_lcl",
+    "day_lcl",
+    "hour_lcl",
+];
+
+/// The primary key of the `cli_fcst` table.
+const FCST_PRIMARY_KEY: [&str; 4] = ["station_num", "model", "init_time", "lead_time"];
+
+/// Get the id of a set of elements in the `element_sets` table, adding it if it is not already
+/// there.
+fn element_set_id(
+    add_element_set_query: &mut Statement,
+    element_set_ids: &mut HashMap<String, i64>,
+    elements: &[String],
+) -> Result<i64, rusqlite::Error> {
+    let mut elements = elements.to_vec();
+    elements.sort();
+    elements.dedup();
+    let elements = elements.join(",");
+
+    if let Some(&id) = element_set_ids.get(&elements) {
+        return Ok(id);
+    }
+
+    let id = add_element_set_query.query_row([&elements], |row| row.get(0))?;
+    element_set_ids.insert(elements, id);
+
+    Ok(id)
+}
+
+/// Split a comma separated list from the database, an empty string is an empty list.
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').filter(|item| !item.is_empty())
+}
+
+/// Get the local year, month, day, and hour of a valid time at a site.
+fn local_time(site: &SiteInfo, valid_time: NaiveDateTime) -> (i32, u32, u32, u32) {
+    let lcl_time = site
+        .time_zone
+        .unwrap_or_else(|| FixedOffset::west_opt(0).unwrap())
+        .from_utc_datetime(&valid_time);
+
+    (
+        lcl_time.year(),
+        lcl_time.month(),
+        lcl_time.day(),
+        lcl_time.hour(),
+    )
+}
+
+/// Build the query to add the values of `elements` to `table`.
+///
+/// The `key_columns` are bound first, then the elements. If there is already a row with the same
+/// `primary_key`, only the columns for `elements` are updated.
+fn add_data_sql(
+    table: &str,
+    key_columns: &[&'static str],
+    primary_key: &[&str],
+    elements: impl Iterator<Item = ClimoElement>,
+) -> String {
+    let cols: Vec<String> = key_columns
+        .iter()
+        .copied()
+        .chain(elements.map(ClimoElement::column_name))
+        .map(quote_identifier)
+        .collect();
+
+    let place_holders = vec!["?"; cols.len()].join(", ");
+
+    let on_conflict = if cols.len() == key_columns.len() {
+        "DO NOTHING".to_owned()
+    } else {
+        let updates: Vec<String> = cols[key_columns.len()..]
+            .iter()
+            .map(|col| format!("{col} = excluded.{col}", col = col))
+            .collect();
+        format!("DO UPDATE SET {}", updates.join(", "))
+    };
+
+    format!(
+        "
+            INSERT INTO {table} ({cols})
+            VALUES ({place_holders})
+            ON CONFLICT ({primary_key})
+            {on_conflict}
+        ",
+        table = table,
+        cols = cols.join(", "),
+        place_holders = place_holders,
+        primary_key = primary_key.join(", "),
+        on_conflict = on_conflict,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bufkit_data::StationNumber;
+    use chrono::NaiveDate;
+
+    fn site() -> SiteInfo {
+        SiteInfo {
+            station_num: StationNumber::new(1),
+            name: Some("test".to_owned()),
+            ..SiteInfo::default()
+        }
+    }
+
+    fn init_time() -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2020, 7, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn cli_data(values: Vec<(ClimoElement, Option<f64>)>) -> StatsRecord {
+        StatsRecord::CliData {
+            site: site(),
+            model: Model::GFS,
+            valid_time: init_time(),
+            values,
+        }
+    }
+
+    fn fcst_data(lead_time: u32, values: Vec<(ClimoElement, Option<f64>)>) -> StatsRecord {
+        StatsRecord::FcstData {
+            site: site(),
+            model: Model::GFS,
+            init_time: init_time(),
+            lead_time,
+            values,
+        }
+    }
+
+    fn add(climo_db: &ClimoDB, records: Vec<StatsRecord>) {
+        let mut populate = ClimoPopulateInterface::initialize(climo_db).unwrap();
+        for record in records {
+            populate.add(record).unwrap();
+        }
+        populate.finish().unwrap();
+    }
+
+    /// The HDW and DCAPE in every row of a table.
+    fn rows(climo_db: &ClimoDB, table: &str) -> Vec<(Option<f64>, Option<f64>)> {
+        let mut stmt = climo_db
+            .conn
+            .prepare(&format!("SELECT hdw, dcape FROM {}", table))
+            .unwrap();
+        let rows: Result<Vec<_>, _> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect();
+
+        rows.unwrap()
+    }
+
+    #[test]
+    fn test_add_data_sql() {
+        let sql = add_data_sql(
+            "cli",
+            &CLI_KEY_COLUMNS,
+            &CLI_PRIMARY_KEY,
+            [ClimoElement::HDW, ClimoElement::BlowUpDt].iter().copied(),
+        );
+        assert!(sql.contains("\"hour_lcl\", \"hdw\", \"el_blow_up_dt\")"));
+        assert!(sql.contains("VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"));
+        assert!(sql.contains(
+            "ON CONFLICT (station_num, valid_time, model, year_lcl, month_lcl, day_lcl, hour_lcl)"
+        ));
+        assert!(sql.contains(concat!(
+            "DO UPDATE SET \"hdw\" = excluded.\"hdw\", ",
+            "\"el_blow_up_dt\" = excluded.\"el_blow_up_dt\""
+        )));
+
+        let sql = add_data_sql(
+            "cli",
+            &CLI_KEY_COLUMNS,
+            &CLI_PRIMARY_KEY,
+            std::iter::empty(),
+        );
+        assert!(sql.contains("DO NOTHING"));
+    }
+
+    #[test]
+    fn test_cli_data_upsert() {
+        let climo_db = ClimoDB::in_memory();
+
+        add(
+            &climo_db,
+            vec![cli_data(vec![
+                (ClimoElement::HDW, Some(1.0)),
+                (ClimoElement::DCAPE, Some(2.0)),
+            ])],
+        );
+        assert_eq!(rows(&climo_db, "cli"), vec![(Some(1.0), Some(2.0))]);
+
+        // Only the elements in the record are replaced.
+        add(
+            &climo_db,
+            vec![cli_data(vec![(ClimoElement::HDW, Some(5.0))])],
+        );
+        assert_eq!(rows(&climo_db, "cli"), vec![(Some(5.0), Some(2.0))]);
+
+        add(&climo_db, vec![cli_data(vec![(ClimoElement::DCAPE, None)])]);
+        assert_eq!(rows(&climo_db, "cli"), vec![(Some(5.0), None)]);
+
+        add(&climo_db, vec![cli_data(vec![])]);
+        assert_eq!(rows(&climo_db, "cli"), vec![(Some(5.0), None)]);
+    }
+
+    #[test]
+    fn test_fcst_data_upsert() {
+        let climo_db = ClimoDB::in_memory();
+
+        add(
+            &climo_db,
+            vec![
+                fcst_data(
+                    24,
+                    vec![
+                        (ClimoElement::HDW, Some(1.0)),
+                        (ClimoElement::DCAPE, Some(2.0)),
+                    ],
+                ),
+                fcst_data(48, vec![(ClimoElement::HDW, Some(3.0))]),
+            ],
+        );
+        add(
+            &climo_db,
+            vec![fcst_data(24, vec![(ClimoElement::HDW, Some(5.0))])],
+        );
+
+        assert_eq!(
+            rows(&climo_db, "cli_fcst"),
+            vec![(Some(5.0), Some(2.0)), (Some(3.0), None)]
+        );
+    }
+
+    #[test]
+    fn test_processed_files() {
+        let climo_db = ClimoDB::in_memory();
+        let file = |status, elements: Vec<&str>, lead_times: Vec<u32>| StatsRecord::File {
+            site: site(),
+            model: Model::GFS,
+            init_time: init_time(),
+            status,
+            hash: Some("abc".to_owned()),
+            reason: None,
+            elements: elements.iter().map(|&e| e.to_owned()).collect(),
+            lead_times,
+        };
+
+        add(
+            &climo_db,
+            vec![file(FileStatus::Ok, vec!["pft", "hdw"], vec![48, 24])],
+        );
+        {
+            let mut populate = ClimoPopulateInterface::initialize(&climo_db).unwrap();
+            let files = populate.processed_files_for(&site(), Model::GFS).unwrap();
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].init_time, init_time());
+            assert_eq!(files[0].status, FileStatus::Ok);
+            assert_eq!(files[0].hash.as_deref(), Some("abc"));
+            assert_eq!(files[0].elements, vec!["hdw".to_owned(), "pft".to_owned()]);
+            assert_eq!(files[0].lead_times, vec![24, 48]);
+        }
+
+        // Processing the file again replaces the record of it.
+        add(&climo_db, vec![file(FileStatus::Skipped, vec![], vec![])]);
+        {
+            let mut populate = ClimoPopulateInterface::initialize(&climo_db).unwrap();
+            let files = populate.processed_files_for(&site(), Model::GFS).unwrap();
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].status, FileStatus::Skipped);
+            assert!(files[0].elements.is_empty());
+            assert!(files[0].lead_times.is_empty());
+        }
+
+        // Files recorded before the elements were have the legacy ones.
+        climo_db
+            .conn
+            .execute(
+                "UPDATE processed_files SET status = 'ok', element_set = NULL",
+                [],
+            )
+            .unwrap();
+        let mut populate = ClimoPopulateInterface::initialize(&climo_db).unwrap();
+        let files = populate.processed_files_for(&site(), Model::GFS).unwrap();
+        let legacy: Vec<&str> = ClimoElement::LEGACY
+            .iter()
+            .map(|elem| elem.column_name())
+            .collect();
+        assert_eq!(files[0].elements, legacy);
     }
 }
@@ -0,0 +1,3 @@
+SELECT init_time, status, hash, element_sets.elements, lead_times
+FROM processed_files LEFT JOIN element_sets ON processed_files.element_set = element_sets.id
+WHERE station_num = ?1 AND model = ?2
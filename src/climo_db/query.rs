@@ -0,0 +1,650 @@
+use super::{quote_identifier, ClimoDB, ClimoElement, FileStatus};
+use bufkit_data::{Model, SiteInfo};
+use chrono::{Datelike, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use rusqlite::types::ToSql;
+use std::error::Error;
+
+/// Forecast values paired with the analysis values they verify against, by valid time.
+pub type ForecastPairs = Vec<(NaiveDateTime, f64, f64)>;
+
+/// Values from two models for the same site, paired by valid time.
+pub type ModelPairs = Vec<(NaiveDateTime, f64, f64)>;
+
+/// The init times of files that could not be processed, with their status and the reason.
+pub type BadFiles = Vec<(NaiveDateTime, FileStatus, Option<String>)>;
+
+/// Restrict the rows returned by a query of the climo database.
+///
+/// All ranges are inclusive. An empty filter matches every row for a site and model.
+#[derive(Clone, Debug, Default)]
+pub struct ClimoQueryFilter {
+    utc_range: Option<(NaiveDateTime, NaiveDateTime)>,
+    local_date_range: Option<(NaiveDate, NaiveDate)>,
+    local_hour_range: Option<(u32, u32)>,
+    months: Option<Vec<u32>>,
+    day_of_year_window: Option<(u32, u32)>,
+    lead_time: Option<u32>,
+}
+
+impl ClimoQueryFilter {
+    /// Create a filter that matches everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only match valid times between `start` and `end` (UTC).
+    pub fn with_utc_range(mut self, start: NaiveDateTime, end: NaiveDateTime) -> Self {
+        self.utc_range = Some((start, end));
+        self
+    }
+
+    /// Only match rows with a local date between `start` and `end`.
+    pub fn with_local_date_range(mut self, start: NaiveDate, end: NaiveDate) -> Self {
+        self.local_date_range = Some((start, end));
+        self
+    }
+
+    /// Only match rows with a local hour between `start` and `end`. If `start` is greater than
+    /// `end` the range wraps around midnight, e.g. 22 to 2.
+    pub fn with_local_hour_range(mut self, start: u32, end: u32) -> Self {
+        self.local_hour_range = Some((start, end));
+        self
+    }
+
+    /// Only match rows with a local month (1-12) in `months`, e.g. `&[12, 1, 2]` for winter.
+    pub fn with_months(mut self, months: &[u32]) -> Self {
+        self.months = Some(months.to_vec());
+        self
+    }
+
+    /// Only match rows with a local calendar day within `half_width` days of the calendar day of
+    /// `center`, wrapping around the end of the year. The year of `center` is ignored.
+    pub fn with_day_of_year_window(mut self, center: NaiveDate, half_width: u32) -> Self {
+        self.day_of_year_window = Some((day_of_year(center.month(), center.day()), half_width));
+        self
+    }
+
+    /// Match the forecasts stored for a lead time (hours) instead of the analyses. Only lead times
+    /// chosen when the database was built are available.
+    pub fn with_lead_time(mut self, lead_time: u32) -> Self {
+        self.lead_time = Some(lead_time);
+        self
+    }
+
+    /// The table to query.
+    fn table(&self) -> &'static str {
+        if self.lead_time.is_some() {
+            "cli_fcst"
+        } else {
+            "cli"
+        }
+    }
+
+    /// Build the extra conditions for the WHERE clause of a query along with their parameters.
+    fn where_clause(&self) -> (String, Vec<Box<dyn ToSql>>) {
+        let mut clause = String::new();
+        let mut params: Vec<Box<dyn ToSql>> = vec![];
+
+        if let Some((start, end)) = self.utc_range {
+            clause += " AND valid_time BETWEEN ? AND ?";
+            params.push(Box::new(start));
+            params.push(Box::new(end));
+        }
+
+        if let Some((start, end)) = self.local_date_range {
+            let as_int = |d: NaiveDate| d.year() * 10_000 + d.month() as i32 * 100 + d.day() as i32;
+
+            clause += " AND (year_lcl * 10000 + month_lcl * 100 + day_lcl) BETWEEN ? AND ?";
+            params.push(Box::new(as_int(start)));
+            params.push(Box::new(as_int(end)));
+        }
+
+        if let Some((start, end)) = self.local_hour_range {
+            if start <= end {
+                clause += " AND hour_lcl BETWEEN ? AND ?";
+            } else {
+                clause += " AND (hour_lcl >= ? OR hour_lcl <= ?)";
+            }
+            params.push(Box::new(start));
+            params.push(Box::new(end));
+        }
+
+        if let Some(ref months) = self.months {
+            let place_holders = vec!["?"; months.len()].join(", ");
+            clause += &format!(" AND month_lcl IN ({})", place_holders);
+            for &month in months {
+                params.push(Box::new(month));
+            }
+        }
+
+        if let Some((center, half_width)) = self.day_of_year_window {
+            // Days are numbered as in a leap year so a calendar day always has the same number.
+            clause += concat!(
+                " AND MIN(ABS(strftime('%j', printf('2000-%02d-%02d', month_lcl, day_lcl)) - ?),",
+                " 366 - ABS(strftime('%j', printf('2000-%02d-%02d', month_lcl, day_lcl)) - ?))",
+                " <= ?"
+            );
+            params.push(Box::new(center));
+            params.push(Box::new(center));
+            params.push(Box::new(half_width));
+        }
+
+        if let Some(lead_time) = self.lead_time {
+            clause += " AND lead_time = ?";
+            params.push(Box::new(lead_time));
+        }
+
+        (clause, params)
+    }
+}
+
+/// The day of the year for a calendar day, numbered as if it were in a leap year.
+fn day_of_year(month: u32, day: u32) -> u32 {
+    NaiveDate::from_ymd_opt(2000, month, day)
+        .map(|d| d.ordinal())
+        .unwrap_or(1)
+}
+
+/// Read data back out of the climo database.
+pub struct ClimoQueryInterface<'a> {
+    climo_db: &'a ClimoDB,
+}
+
+impl<'a> ClimoQueryInterface<'a> {
+    pub fn initialize(climo_db: &'a ClimoDB) -> Self {
+        ClimoQueryInterface { climo_db }
+    }
+
+    /// Get the time series of an element for a site and model, sorted by valid time.
+    ///
+    /// Rows where the element is missing are skipped.
+    pub fn time_series(
+        &self,
+        site: &SiteInfo,
+        model: Model,
+        element: ClimoElement,
+        filter: &ClimoQueryFilter,
+    ) -> Result<Vec<(NaiveDateTime, f64)>, Box<dyn Error>> {
+        let col_name = quote_identifier(element.column_name());
+
+        let (where_clause, filter_params) = filter.where_clause();
+        let query = format!(
+            "
+                SELECT valid_time, {col}
+                FROM {table}
+                WHERE station_num = ? AND model = ? AND {col} IS NOT NULL{filter}
+                ORDER BY valid_time ASC
+            ",
+            col = col_name,
+            table = filter.table(),
+            filter = where_clause
+        );
+
+        let station_num: u32 = site.station_num.into();
+        let model_str = model.as_static_str();
+
+        let mut params: Vec<&dyn ToSql> = vec![&station_num, &model_str];
+        params.extend(filter_params.iter().map(|p| p as &dyn ToSql));
+
+        let mut stmt = self.climo_db.conn.prepare_cached(&query)?;
+        let vals: Result<Vec<(NaiveDateTime, f64)>, _> = stmt
+            .query_map(params.as_slice(), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect();
+
+        Ok(vals?)
+    }
+
+    /// Pair the forecasts of an element at a lead time (hours) with the analysis value at the same
+    /// valid time from the same model, sorted by valid time.
+    ///
+    /// The result is `(valid_time, forecast, analysis)`. Forecasts without a matching analysis are
+    /// skipped.
+    pub fn forecast_pairs(
+        &self,
+        site: &SiteInfo,
+        model: Model,
+        element: ClimoElement,
+        lead_time: u32,
+        filter: &ClimoQueryFilter,
+    ) -> Result<ForecastPairs, Box<dyn Error>> {
+        let col_name = quote_identifier(element.column_name());
+
+        // The filter applies to the forecast rows, so join in a sub-query to keep its column
+        // names unambiguous.
+        let (where_clause, filter_params) = filter.where_clause();
+        let query = format!(
+            "
+                SELECT valid_time, fcst, anal
+                FROM (
+                    SELECT f.valid_time, f.year_lcl, f.month_lcl, f.day_lcl, f.hour_lcl,
+                        f.lead_time, f.{col} AS fcst, a.{col} AS anal
+                    FROM cli_fcst AS f
+                    JOIN cli AS a
+                    ON a.station_num = f.station_num AND a.model = f.model
+                        AND a.valid_time = f.valid_time
+                    WHERE f.station_num = ? AND f.model = ? AND f.lead_time = ?
+                        AND f.{col} IS NOT NULL AND a.{col} IS NOT NULL
+                )
+                WHERE 1{filter}
+                ORDER BY valid_time ASC
+            ",
+            col = col_name,
+            filter = where_clause
+        );
+
+        let station_num: u32 = site.station_num.into();
+        let model_str = model.as_static_str();
+
+        let mut params: Vec<&dyn ToSql> = vec![&station_num, &model_str, &lead_time];
+        params.extend(filter_params.iter().map(|p| p as &dyn ToSql));
+
+        let mut stmt = self.climo_db.conn.prepare_cached(&query)?;
+        let vals: Result<ForecastPairs, _> = stmt
+            .query_map(params.as_slice(), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect();
+
+        Ok(vals?)
+    }
+
+    /// Pair the values of an element from two models for a site at the same valid time, sorted by
+    /// valid time.
+    ///
+    /// The result is `(valid_time, value_a, value_b)`. Times missing from either model are skipped.
+    /// If the filter has a lead time, the forecasts at that lead time are paired instead of the
+    /// analyses.
+    pub fn model_pairs(
+        &self,
+        site: &SiteInfo,
+        model_a: Model,
+        model_b: Model,
+        element: ClimoElement,
+        filter: &ClimoQueryFilter,
+    ) -> Result<ModelPairs, Box<dyn Error>> {
+        let col_name = quote_identifier(element.column_name());
+
+        let lead_time_join = if filter.lead_time.is_some() {
+            " AND b.lead_time = a.lead_time"
+        } else {
+            ""
+        };
+
+        // The filter applies to the rows of model_a, so join in a sub-query to keep its column
+        // names unambiguous.
+        let (where_clause, filter_params) = filter.where_clause();
+        let query = format!(
+            "
+                SELECT valid_time, {col}, val_b
+                FROM (
+                    SELECT a.*, b.{col} AS val_b
+                    FROM {table} AS a
+                    JOIN {table} AS b
+                    ON b.station_num = a.station_num AND b.valid_time = a.valid_time{lead}
+                    WHERE a.station_num = ? AND a.model = ? AND b.model = ?
+                        AND a.{col} IS NOT NULL AND b.{col} IS NOT NULL
+                )
+                WHERE 1{filter}
+                ORDER BY valid_time ASC
+            ",
+            col = col_name,
+            table = filter.table(),
+            lead = lead_time_join,
+            filter = where_clause
+        );
+
+        let station_num: u32 = site.station_num.into();
+        let model_a_str = model_a.as_static_str();
+        let model_b_str = model_b.as_static_str();
+
+        let mut params: Vec<&dyn ToSql> = vec![&station_num, &model_a_str, &model_b_str];
+        params.extend(filter_params.iter().map(|p| p as &dyn ToSql));
+
+        let mut stmt = self.climo_db.conn.prepare_cached(&query)?;
+        let vals: Result<ModelPairs, _> = stmt
+            .query_map(params.as_slice(), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect();
+
+        Ok(vals?)
+    }
+
+    /// Get the files for a site and model that could not be processed, sorted by init time.
+    pub fn bad_files(&self, site: &SiteInfo, model: Model) -> Result<BadFiles, Box<dyn Error>> {
+        let station_num: u32 = site.station_num.into();
+        let model_str = model.as_static_str();
+
+        let mut stmt = self
+            .climo_db
+            .conn
+            .prepare_cached(include_str!("bad_files.sql"))?;
+        let files: Result<Vec<(NaiveDateTime, String, Option<String>)>, _> = stmt
+            .query_map([&station_num as &dyn ToSql, &model_str], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect();
+
+        files?
+            .into_iter()
+            .map(|(init_time, status, reason)| Ok((init_time, status.parse()?, reason)))
+            .collect()
+    }
+
+    /// Get the percentile rank (0-100) of `value` against the climatology of an element.
+    ///
+    /// The climatology is every stored value with a local day of year within `window_days` of the
+    /// local day of year of `valid_time`. If `same_hour` is true, it is further limited to values
+    /// at the same local hour as `valid_time`. Returns `None` if there is no climatology to rank
+    /// against.
+    #[allow(clippy::too_many_arguments)]
+    pub fn percentile_rank(
+        &self,
+        site: &SiteInfo,
+        model: Model,
+        element: ClimoElement,
+        valid_time: NaiveDateTime,
+        value: f64,
+        window_days: u32,
+        same_hour: bool,
+    ) -> Result<Option<f64>, Box<dyn Error>> {
+        let lcl_time = site
+            .time_zone
+            .unwrap_or_else(|| FixedOffset::west_opt(0).unwrap())
+            .from_utc_datetime(&valid_time);
+
+        let mut filter =
+            ClimoQueryFilter::new().with_day_of_year_window(lcl_time.date_naive(), window_days);
+        if same_hour {
+            filter = filter.with_local_hour_range(lcl_time.hour(), lcl_time.hour());
+        }
+
+        let climo = self.values(site, model, element, &filter)?;
+
+        Ok(crate::stats::percentile_rank(value, &climo))
+    }
+
+    /// Get the parameters recorded for an element, e.g. the mean wind speed of a PFT variant.
+    pub fn element_params(
+        &self,
+        element: ClimoElement,
+    ) -> Result<Vec<(String, f64)>, Box<dyn Error>> {
+        let mut stmt = self
+            .climo_db
+            .conn
+            .prepare_cached(include_str!("element_params.sql"))?;
+        let params: Result<Vec<(String, f64)>, _> = stmt
+            .query_map([element.column_name()], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })?
+            .collect();
+
+        Ok(params?)
+    }
+
+    /// Get just the values of an element for a site and model without the valid times.
+    pub fn values(
+        &self,
+        site: &SiteInfo,
+        model: Model,
+        element: ClimoElement,
+        filter: &ClimoQueryFilter,
+    ) -> Result<Vec<f64>, Box<dyn Error>> {
+        let vals = self
+            .time_series(site, model, element, filter)?
+            .into_iter()
+            .map(|(_, val)| val)
+            .collect();
+
+        Ok(vals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClimoPopulateInterface, StatsRecord};
+    use bufkit_data::StationNumber;
+
+    /// A site 7 hours behind UTC.
+    fn site() -> SiteInfo {
+        SiteInfo {
+            station_num: StationNumber::new(1),
+            name: Some("test".to_owned()),
+            time_zone: Some(FixedOffset::west_opt(7 * 3600).unwrap()),
+            ..SiteInfo::default()
+        }
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    /// A database with the HDW at `valid_times`, numbered in order.
+    fn climo_db(valid_times: &[NaiveDateTime]) -> ClimoDB {
+        let climo_db = ClimoDB::in_memory();
+        let mut populate = ClimoPopulateInterface::initialize(&climo_db).unwrap();
+        for (i, &valid_time) in valid_times.iter().enumerate() {
+            populate
+                .add(StatsRecord::CliData {
+                    site: site(),
+                    model: Model::GFS,
+                    valid_time,
+                    values: vec![(ClimoElement::HDW, Some(i as f64))],
+                })
+                .unwrap();
+        }
+        populate.finish().unwrap();
+
+        climo_db
+    }
+
+    /// The analysis of the HDW from `model` at `valid_time`.
+    fn anal(model: Model, valid_time: NaiveDateTime, hdw: f64) -> StatsRecord {
+        StatsRecord::CliData {
+            site: site(),
+            model,
+            valid_time,
+            values: vec![(ClimoElement::HDW, Some(hdw))],
+        }
+    }
+
+    /// The forecast of the HDW from `model` at `init_time` plus `lead_time` hours.
+    fn fcst(model: Model, init_time: NaiveDateTime, lead_time: u32, hdw: f64) -> StatsRecord {
+        StatsRecord::FcstData {
+            site: site(),
+            model,
+            init_time,
+            lead_time,
+            values: vec![(ClimoElement::HDW, Some(hdw))],
+        }
+    }
+
+    /// A database with the records in it.
+    fn populated(records: Vec<StatsRecord>) -> ClimoDB {
+        let climo_db = ClimoDB::in_memory();
+        let mut populate = ClimoPopulateInterface::initialize(&climo_db).unwrap();
+        for record in records {
+            populate.add(record).unwrap();
+        }
+        populate.finish().unwrap();
+
+        climo_db
+    }
+
+    fn hdw(climo_db: &ClimoDB, filter: &ClimoQueryFilter) -> Vec<f64> {
+        ClimoQueryInterface::initialize(climo_db)
+            .values(&site(), Model::GFS, ClimoElement::HDW, filter)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_day_of_year_window_wraps_around_the_year() {
+        let climo_db = climo_db(&[
+            utc(2019, 12, 28, 19),
+            utc(2019, 12, 30, 19),
+            utc(2019, 12, 31, 19),
+            utc(2020, 1, 1, 19),
+            utc(2020, 1, 3, 19),
+            utc(2020, 1, 4, 19),
+        ]);
+
+        let filter = ClimoQueryFilter::new()
+            .with_day_of_year_window(NaiveDate::from_ymd_opt(2021, 1, 1).unwrap(), 2);
+        assert_eq!(hdw(&climo_db, &filter), vec![1.0, 2.0, 3.0, 4.0]);
+
+        let filter = ClimoQueryFilter::new()
+            .with_day_of_year_window(NaiveDate::from_ymd_opt(2021, 12, 29).unwrap(), 1);
+        assert_eq!(hdw(&climo_db, &filter), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_day_of_year_window_uses_local_days() {
+        // 03Z on Jan 2nd is the evening of Jan 1st locally.
+        let climo_db = climo_db(&[utc(2020, 1, 2, 3), utc(2020, 1, 2, 12)]);
+
+        let filter = ClimoQueryFilter::new()
+            .with_day_of_year_window(NaiveDate::from_ymd_opt(2020, 1, 1).unwrap(), 0);
+        assert_eq!(hdw(&climo_db, &filter), vec![0.0]);
+
+        let filter = filter.with_local_hour_range(20, 20);
+        assert_eq!(hdw(&climo_db, &filter), vec![0.0]);
+    }
+
+    #[test]
+    fn test_day_of_year_window_leap_day() {
+        let climo_db = climo_db(&[
+            utc(2019, 2, 28, 19),
+            utc(2020, 2, 29, 19),
+            utc(2021, 3, 1, 19),
+            utc(2021, 3, 2, 19),
+        ]);
+
+        // Days are numbered as in a leap year, so Feb 28th and Mar 1st are 2 days apart every year.
+        let filter = ClimoQueryFilter::new()
+            .with_day_of_year_window(NaiveDate::from_ymd_opt(2021, 3, 1).unwrap(), 1);
+        assert_eq!(hdw(&climo_db, &filter), vec![1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_percentile_rank() {
+        let valid_times: Vec<NaiveDateTime> = (0..4)
+            .flat_map(|year| vec![utc(2016 + year, 7, 1, 19), utc(2016 + year, 7, 2, 7)])
+            .collect();
+        let climo_db = climo_db(&valid_times);
+        let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+        // The afternoon values are 0, 2, 4, and 6.
+        let rank = climo_db
+            .percentile_rank(
+                &site(),
+                Model::GFS,
+                ClimoElement::HDW,
+                utc(2021, 7, 1, 19),
+                4.0,
+                1,
+                true,
+            )
+            .unwrap();
+        assert_eq!(rank, Some(62.5));
+
+        let rank = climo_db
+            .percentile_rank(
+                &site(),
+                Model::GFS,
+                ClimoElement::HDW,
+                utc(2021, 7, 1, 19),
+                4.0,
+                1,
+                false,
+            )
+            .unwrap();
+        assert_eq!(rank, Some(56.25));
+    }
+
+    #[test]
+    fn test_forecast_pairs() {
+        let climo_db = populated(vec![
+            anal(Model::GFS, utc(2020, 7, 1, 0), 0.0),
+            anal(Model::GFS, utc(2020, 7, 1, 6), 6.0),
+            anal(Model::GFS, utc(2020, 7, 1, 12), 12.0),
+            anal(Model::NAM, utc(2020, 7, 1, 6), 60.0),
+            fcst(Model::GFS, utc(2020, 7, 1, 6), 6, 11.0),
+            fcst(Model::GFS, utc(2020, 7, 1, 0), 6, 7.0),
+            fcst(Model::GFS, utc(2020, 7, 1, 0), 12, 13.0),
+            fcst(Model::NAM, utc(2020, 7, 1, 0), 6, 70.0),
+            // No analysis at this valid time.
+            fcst(Model::GFS, utc(2020, 7, 1, 18), 6, 1.0),
+        ]);
+        let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+        let pairs = |lead_time, filter: &ClimoQueryFilter| {
+            climo_db
+                .forecast_pairs(&site(), Model::GFS, ClimoElement::HDW, lead_time, filter)
+                .unwrap()
+        };
+
+        let filter = ClimoQueryFilter::new();
+        assert_eq!(
+            pairs(6, &filter),
+            vec![
+                (utc(2020, 7, 1, 6), 7.0, 6.0),
+                (utc(2020, 7, 1, 12), 11.0, 12.0)
+            ]
+        );
+        assert_eq!(pairs(12, &filter), vec![(utc(2020, 7, 1, 12), 13.0, 12.0)]);
+
+        // 12Z is 05 local time.
+        let filter = filter.with_local_hour_range(5, 5);
+        assert_eq!(pairs(6, &filter), vec![(utc(2020, 7, 1, 12), 11.0, 12.0)]);
+    }
+
+    #[test]
+    fn test_model_pairs() {
+        let climo_db = populated(vec![
+            anal(Model::GFS, utc(2020, 7, 1, 12), 12.0),
+            anal(Model::GFS, utc(2020, 7, 1, 6), 6.0),
+            anal(Model::GFS, utc(2020, 7, 1, 0), 0.0),
+            anal(Model::NAM, utc(2020, 7, 1, 6), 60.0),
+            anal(Model::NAM, utc(2020, 7, 1, 12), 120.0),
+            anal(Model::NAM, utc(2020, 7, 1, 18), 180.0),
+            fcst(Model::GFS, utc(2020, 7, 1, 0), 6, 7.0),
+            fcst(Model::GFS, utc(2020, 7, 1, 6), 6, 13.0),
+            fcst(Model::NAM, utc(2020, 7, 1, 0), 6, 70.0),
+            fcst(Model::NAM, utc(2020, 7, 1, 0), 12, 130.0),
+        ]);
+        let climo_db = ClimoQueryInterface::initialize(&climo_db);
+
+        let pairs = |model_a, model_b, filter: &ClimoQueryFilter| {
+            climo_db
+                .model_pairs(&site(), model_a, model_b, ClimoElement::HDW, filter)
+                .unwrap()
+        };
+
+        let filter = ClimoQueryFilter::new();
+        assert_eq!(
+            pairs(Model::GFS, Model::NAM, &filter),
+            vec![
+                (utc(2020, 7, 1, 6), 6.0, 60.0),
+                (utc(2020, 7, 1, 12), 12.0, 120.0)
+            ]
+        );
+        assert_eq!(
+            pairs(Model::NAM, Model::GFS, &filter),
+            vec![
+                (utc(2020, 7, 1, 6), 60.0, 6.0),
+                (utc(2020, 7, 1, 12), 120.0, 12.0)
+            ]
+        );
+
+        // Forecasts are only paired at the same lead time.
+        let filter = filter.with_lead_time(6);
+        assert_eq!(
+            pairs(Model::GFS, Model::NAM, &filter),
+            vec![(utc(2020, 7, 1, 6), 7.0, 70.0)]
+        );
+    }
+}
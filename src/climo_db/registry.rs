@@ -0,0 +1,593 @@
+use super::{migrate, populate, quote_identifier, ClimoDB, ClimoElement};
+use crate::BufcliError;
+use rusqlite::types::ToSql;
+use sounding_analysis::Sounding;
+use std::error::Error;
+
+/// A calculation for an element that is not built into bufcli.
+///
+/// Register it with a `ClimoElementRegistry` to get a `ClimoElement` for it that can be computed,
+/// stored and queried like any other.
+pub trait ClimoElementCalc: Send + Sync {
+    /// A short, lowercase name for the element. This is also the name of its column in the
+    /// database, so it may only contain ASCII letters, digits and underscores.
+    fn name(&self) -> &'static str;
+
+    /// The SQL type of the column, e.g. "REAL" or "INT".
+    fn sql_type(&self) -> &'static str {
+        "REAL"
+    }
+
+    /// Parameters of the calculation, recorded in the database with the element.
+    fn params(&self) -> Vec<(&'static str, f64)> {
+        vec![]
+    }
+
+    /// Calculate the value of the element for a sounding.
+    fn compute(&self, snd: &Sounding) -> Option<f64>;
+}
+
+impl PartialEq for dyn ClimoElementCalc {
+    fn eq(&self, other: &Self) -> bool {
+        self.name() == other.name()
+    }
+}
+
+impl Eq for dyn ClimoElementCalc {}
+
+impl std::fmt::Debug for dyn ClimoElementCalc {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "ClimoElementCalc({})", self.name())
+    }
+}
+
+/// The set of elements to compute, store and query.
+///
+/// It starts with all the built in elements, and more can be added with `register`.
+#[derive(Clone, Debug)]
+pub struct ClimoElementRegistry {
+    elements: Vec<ClimoElement>,
+    pft_moisture_ratio: Option<f64>,
+}
+
+impl Default for ClimoElementRegistry {
+    fn default() -> Self {
+        ClimoElementRegistry {
+            elements: ClimoElement::ALL.to_vec(),
+            pft_moisture_ratio: None,
+        }
+    }
+}
+
+impl ClimoElementRegistry {
+    /// The heating needed to add 1 g/kg of moisture to a plume that the PFT elements use unless
+    /// another is set.
+    pub const DEFAULT_PFT_MOISTURE_RATIO: f64 = 15.0;
+
+    /// Create a registry with all the built in elements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an element calculation, returning the element for it.
+    pub fn register(
+        &mut self,
+        calc: &'static dyn ClimoElementCalc,
+    ) -> Result<ClimoElement, BufcliError> {
+        let name = calc.name();
+
+        let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_name {
+            return Err(BufcliError::new("Invalid element name"));
+        }
+
+        // Key columns of the tables and SQL keywords can't be used as column names.
+        let reserved = populate::CLI_KEY_COLUMNS
+            .iter()
+            .chain(populate::FCST_KEY_COLUMNS.iter())
+            .chain(SQL_KEYWORDS.iter())
+            .any(|word| word.eq_ignore_ascii_case(name));
+        if reserved {
+            return Err(BufcliError::new("Element name is reserved"));
+        }
+
+        // Built in elements may have been dropped from this registry by `select`, but their
+        // names and columns are still taken. Column names are not case sensitive in SQLite.
+        let taken = |elem: &ClimoElement| {
+            elem.as_static_str().eq_ignore_ascii_case(name)
+                || elem.column_name().eq_ignore_ascii_case(name)
+        };
+        if self.elements.iter().any(taken) || ClimoElement::ALL.iter().any(taken) {
+            return Err(BufcliError::new("Element name already in use"));
+        }
+
+        let element = ClimoElement::Custom(calc);
+        self.elements.push(element);
+
+        Ok(element)
+    }
+
+    /// Add a PFT variant that assumes a fixed mean wind speed (m/s) in the mixed layer instead of
+    /// the one in the sounding, returning the element for it. It is named `pft_<speed>mps`.
+    pub fn add_pft(&mut self, mean_wind_mps: u32) -> Result<ClimoElement, BufcliError> {
+        let name = format!("pft_{}mps", mean_wind_mps);
+        if self.find(&name).is_some() {
+            return Err(BufcliError::new("Element name already in use"));
+        }
+
+        // Elements are cheap to copy around because they only hold static references, so
+        // variants created at runtime live for the rest of the program.
+        let element = ClimoElement::FixedWindPFT {
+            name: Box::leak(name.into_boxed_str()),
+            mean_wind_mps,
+        };
+        self.elements.push(element);
+
+        Ok(element)
+    }
+
+    /// Set the heating needed to add 1 g/kg of moisture to a plume for all the PFT elements.
+    ///
+    /// If it is not set, the one recorded in the database with the PFT elements is used. Values
+    /// already in the database are not calculated again, use recompute for that.
+    pub fn set_pft_moisture_ratio(&mut self, moisture_ratio: f64) {
+        self.pft_moisture_ratio = Some(moisture_ratio);
+    }
+
+    /// The heating needed to add 1 g/kg of moisture to a plume for all the PFT elements.
+    pub fn pft_moisture_ratio(&self) -> f64 {
+        self.pft_moisture_ratio
+            .unwrap_or(Self::DEFAULT_PFT_MOISTURE_RATIO)
+    }
+
+    /// Only keep `elements`, in that order, so nothing else is computed or stored.
+    ///
+    /// Fails if any of them is not in the registry.
+    pub fn select(&mut self, elements: &[ClimoElement]) -> Result<(), BufcliError> {
+        if elements.iter().any(|elem| !self.elements.contains(elem)) {
+            return Err(BufcliError::new("Element not in registry"));
+        }
+
+        self.elements = elements.to_vec();
+
+        Ok(())
+    }
+
+    /// All the elements in the registry.
+    pub fn elements(&self) -> &[ClimoElement] {
+        &self.elements
+    }
+
+    /// Find an element by the name used on the command line, ignoring case.
+    pub fn find(&self, name: &str) -> Option<ClimoElement> {
+        let name = name.to_lowercase();
+        self.elements
+            .iter()
+            .copied()
+            .find(|elem| elem.as_static_str() == name)
+    }
+
+    /// Find the element stored in a column of the `cli` table.
+    pub fn find_column(&self, col_name: &str) -> Option<ClimoElement> {
+        self.elements
+            .iter()
+            .copied()
+            .find(|elem| elem.column_name() == col_name)
+    }
+
+    /// Add columns for the elements in the registry to the database if they are not already
+    /// there, and record the parameters of their calculations.
+    ///
+    /// If the PFT moisture ratio was not set, the one recorded for the PFT elements is used. The
+    /// parameters of elements with values in the database are only replaced if `recompute` is set,
+    /// because those values were calculated with the recorded ones. Otherwise it fails if they
+    /// differ.
+    pub fn create_columns(
+        &mut self,
+        climo_db: &ClimoDB,
+        recompute: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        migrate::add_element_columns(&climo_db.conn, &self.elements)?;
+
+        let mut stored_params_stmt = climo_db.conn.prepare(include_str!("element_params.sql"))?;
+        let mut stored_params = Vec::with_capacity(self.elements.len());
+        for &elem in self.elements.iter() {
+            let params: Result<Vec<(String, f64)>, _> = stored_params_stmt
+                .query_map([elem.column_name()], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect();
+            stored_params.push((elem, params?));
+        }
+
+        if self.pft_moisture_ratio.is_none() {
+            let mut ratios = stored_params
+                .iter()
+                .flat_map(|(_, params)| params.iter())
+                .filter(|(param, _)| param == "moisture_ratio")
+                .map(|&(_, value)| value);
+
+            if let Some(ratio) = ratios.next() {
+                if ratios.any(|other| other != ratio) {
+                    return Err(BufcliError::new(
+                        "PFT elements in the database use different moisture ratios",
+                    )
+                    .into());
+                }
+                self.pft_moisture_ratio = Some(ratio);
+            }
+        }
+
+        let mut add_param_stmt = climo_db
+            .conn
+            .prepare(include_str!("add_element_param.sql"))?;
+        for (elem, stored) in stored_params {
+            let mut params: Vec<(String, f64)> = self
+                .params(elem)
+                .into_iter()
+                .map(|(param, value)| (param.to_owned(), value))
+                .collect();
+            params.sort_by(|a, b| a.0.cmp(&b.0));
+
+            if !recompute && !stored.is_empty() && has_values(climo_db, elem)? {
+                if stored != params {
+                    return Err(BufcliError::new(
+                        "Element parameters differ from the database, recompute the elements",
+                    )
+                    .into());
+                }
+                continue;
+            }
+
+            for (param, value) in params {
+                add_param_stmt.execute([&elem.column_name() as &dyn ToSql, &param, &value])?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parameters of the calculation of an element, recorded in the database with it.
+    fn params(&self, element: ClimoElement) -> Vec<(&'static str, f64)> {
+        match element {
+            ClimoElement::PFT => vec![("moisture_ratio", self.pft_moisture_ratio())],
+            ClimoElement::FixedWindPFT { mean_wind_mps, .. } => vec![
+                ("mean_wind_mps", f64::from(mean_wind_mps)),
+                ("moisture_ratio", self.pft_moisture_ratio()),
+            ],
+            ClimoElement::Custom(calc) => calc.params(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Check if any values of an element are stored in the database.
+fn has_values(climo_db: &ClimoDB, element: ClimoElement) -> Result<bool, Box<dyn Error>> {
+    let col = quote_identifier(element.column_name());
+    let query = migrate::ELEMENT_TABLES
+        .iter()
+        .map(|table| format!("EXISTS (SELECT 1 FROM {} WHERE {} IS NOT NULL)", table, col))
+        .collect::<Vec<_>>()
+        .join(" OR ");
+
+    let has_values = climo_db
+        .conn
+        .query_row(&format!("SELECT {}", query), [], |row| row.get(0))?;
+
+    Ok(has_values)
+}
+
+/// SQLite keywords and the aliases of the row id, which can't be used as element names.
+const SQL_KEYWORDS: [&str; 150] = [
+    "abort",
+    "action",
+    "add",
+    "after",
+    "all",
+    "alter",
+    "always",
+    "analyze",
+    "and",
+    "as",
+    "asc",
+    "attach",
+    "autoincrement",
+    "before",
+    "begin",
+    "between",
+    "by",
+    "cascade",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "column",
+    "commit",
+    "conflict",
+    "constraint",
+    "create",
+    "cross",
+    "current",
+    "current_date",
+    "current_time",
+    "current_timestamp",
+    "database",
+    "default",
+    "deferrable",
+    "deferred",
+    "delete",
+    "desc",
+    "detach",
+    "distinct",
+    "do",
+    "drop",
+    "each",
+    "else",
+    "end",
+    "escape",
+    "except",
+    "exclude",
+    "exclusive",
+    "exists",
+    "explain",
+    "fail",
+    "filter",
+    "first",
+    "following",
+    "for",
+    "foreign",
+    "from",
+    "full",
+    "generated",
+    "glob",
+    "group",
+    "groups",
+    "having",
+    "if",
+    "ignore",
+    "immediate",
+    "in",
+    "index",
+    "indexed",
+    "initially",
+    "inner",
+    "insert",
+    "instead",
+    "intersect",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "key",
+    "last",
+    "left",
+    "like",
+    "limit",
+    "match",
+    "materialized",
+    "natural",
+    "no",
+    "not",
+    "nothing",
+    "notnull",
+    "null",
+    "nulls",
+    "of",
+    "offset",
+    "on",
+    "or",
+    "order",
+    "others",
+    "outer",
+    "over",
+    "partition",
+    "plan",
+    "pragma",
+    "preceding",
+    "primary",
+    "query",
+    "raise",
+    "range",
+    "recursive",
+    "references",
+    "regexp",
+    "reindex",
+    "release",
+    "rename",
+    "replace",
+    "restrict",
+    "returning",
+    "right",
+    "rollback",
+    "row",
+    "rows",
+    "savepoint",
+    "select",
+    "set",
+    "table",
+    "temp",
+    "temporary",
+    "then",
+    "ties",
+    "to",
+    "transaction",
+    "trigger",
+    "unbounded",
+    "union",
+    "unique",
+    "update",
+    "using",
+    "vacuum",
+    "values",
+    "view",
+    "virtual",
+    "when",
+    "where",
+    "window",
+    "with",
+    "without",
+    "rowid",
+    "oid",
+    "_rowid_",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClimoQueryInterface;
+
+    struct TestCalc(&'static str);
+
+    impl ClimoElementCalc for TestCalc {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn params(&self) -> Vec<(&'static str, f64)> {
+            vec![("scale", 2.0)]
+        }
+
+        fn compute(&self, _snd: &Sounding) -> Option<f64> {
+            None
+        }
+    }
+
+    static STN_PRES: TestCalc = TestCalc("stn_pres");
+
+    #[test]
+    fn test_register() {
+        let mut registry = ClimoElementRegistry::new();
+
+        let elem = registry.register(&STN_PRES).unwrap();
+        assert_eq!(elem.as_static_str(), "stn_pres");
+        assert_eq!(registry.find("STN_PRES"), Some(elem));
+        assert_eq!(registry.find_column("stn_pres"), Some(elem));
+
+        assert!(registry.register(&STN_PRES).is_err());
+        assert!(registry.register(&TestCalc("bad name")).is_err());
+        assert!(registry.register(&TestCalc("1st")).is_err());
+    }
+
+    #[test]
+    fn test_register_built_in_names() {
+        let mut registry = ClimoElementRegistry::new();
+        registry.select(&[ClimoElement::HDW]).unwrap();
+
+        // Built in names and columns are taken even when they are not selected.
+        assert!(registry.register(&TestCalc("pft")).is_err());
+        assert!(registry.register(&TestCalc("DCAPE")).is_err());
+        assert!(registry.register(&TestCalc("el_blow_up_dz")).is_err());
+    }
+
+    #[test]
+    fn test_register_reserved_names() {
+        let mut registry = ClimoElementRegistry::new();
+
+        assert!(registry.register(&TestCalc("valid_time")).is_err());
+        assert!(registry.register(&TestCalc("LEAD_TIME")).is_err());
+        assert!(registry.register(&TestCalc("hour_lcl")).is_err());
+        assert!(registry.register(&TestCalc("order")).is_err());
+        assert!(registry.register(&TestCalc("Group")).is_err());
+        assert!(registry.register(&TestCalc("rowid")).is_err());
+
+        // Names that only contain a keyword are fine.
+        assert!(registry.register(&TestCalc("order_num")).is_ok());
+    }
+
+    #[test]
+    fn test_select() {
+        let mut registry = ClimoElementRegistry::new();
+        let pft_10 = registry.add_pft(10).unwrap();
+        assert!(registry.add_pft(10).is_err());
+
+        registry.select(&[pft_10, ClimoElement::HDW]).unwrap();
+        assert_eq!(registry.elements(), &[pft_10, ClimoElement::HDW]);
+        assert_eq!(registry.find("pft"), None);
+
+        assert!(registry.select(&[ClimoElement::PFT]).is_err());
+    }
+
+    #[test]
+    fn test_create_columns() {
+        let climo_db = ClimoDB::in_memory();
+
+        let mut registry = ClimoElementRegistry::new();
+        let stn_pres = registry.register(&STN_PRES).unwrap();
+        let pft_10 = registry.add_pft(10).unwrap();
+        registry.set_pft_moisture_ratio(12.0);
+        registry.create_columns(&climo_db, false).unwrap();
+        registry.create_columns(&climo_db, false).unwrap();
+
+        let mut stmt = climo_db
+            .conn
+            .prepare("SELECT name FROM pragma_table_info('cli_fcst')")
+            .unwrap();
+        let columns: Vec<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert!(columns.iter().any(|col| col == "stn_pres"));
+        assert!(columns.iter().any(|col| col == "pft_10mps"));
+
+        let climo_db = ClimoQueryInterface::initialize(&climo_db);
+        assert_eq!(
+            climo_db.element_params(stn_pres).unwrap(),
+            vec![("scale".to_owned(), 2.0)]
+        );
+        assert_eq!(
+            climo_db.element_params(pft_10).unwrap(),
+            vec![
+                ("mean_wind_mps".to_owned(), 10.0),
+                ("moisture_ratio".to_owned(), 12.0)
+            ]
+        );
+        assert_eq!(
+            climo_db.element_params(ClimoElement::PFT).unwrap(),
+            vec![("moisture_ratio".to_owned(), 12.0)]
+        );
+    }
+
+    #[test]
+    fn test_create_columns_with_stored_values() {
+        let climo_db = ClimoDB::in_memory();
+
+        let mut registry = ClimoElementRegistry::new();
+        registry.set_pft_moisture_ratio(12.0);
+        registry.create_columns(&climo_db, true).unwrap();
+        climo_db
+            .conn
+            .execute_batch(concat!(
+                "INSERT INTO cli (station_num, model, valid_time, year_lcl, month_lcl, day_lcl,",
+                " hour_lcl, pft) VALUES (1, 'gfs', '2020-01-01 00:00:00', 2019, 12, 31, 17, 100)"
+            ))
+            .unwrap();
+
+        // The stored ratio is used unless another is set.
+        let mut registry = ClimoElementRegistry::new();
+        let stn_pres = registry.register(&STN_PRES).unwrap();
+        registry.create_columns(&climo_db, false).unwrap();
+        assert_eq!(registry.pft_moisture_ratio(), 12.0);
+
+        // New elements get their parameters recorded without recomputing.
+        let query = ClimoQueryInterface::initialize(&climo_db);
+        assert_eq!(
+            query.element_params(stn_pres).unwrap(),
+            vec![("scale".to_owned(), 2.0)]
+        );
+
+        // Stored values can't be mixed with values calculated with another ratio.
+        let mut registry = ClimoElementRegistry::new();
+        registry.set_pft_moisture_ratio(10.0);
+        assert!(registry.create_columns(&climo_db, false).is_err());
+        assert_eq!(
+            query.element_params(ClimoElement::PFT).unwrap(),
+            vec![("moisture_ratio".to_owned(), 12.0)]
+        );
+
+        registry.create_columns(&climo_db, true).unwrap();
+        assert_eq!(
+            query.element_params(ClimoElement::PFT).unwrap(),
+            vec![("moisture_ratio".to_owned(), 10.0)]
+        );
+    }
+}
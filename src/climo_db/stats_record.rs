@@ -1,7 +1,12 @@
+use super::{ClimoElement, ClimoElementRegistry, FileStatus, LevelElement, MandatoryLevel};
 use bufkit_data::{Model, SiteInfo};
 use chrono::NaiveDateTime;
-use metfor::Quantity;
-use sounding_analysis::{experimental::fire::BlowUpAnalysis, Sounding};
+use metfor::{Celsius, HectoPascal, Meters, MetersPSec, Quantity, WindSpdDir, WindUV};
+use sounding_analysis::{
+    experimental::fire::BlowUpAnalysis, DataRow, Layer, Levels, PFTAnalysis, ParcelAscentAnalysis,
+    Sounding,
+};
+use std::collections::HashMap;
 
 #[derive(Clone, Debug)]
 pub enum StatsRecord {
@@ -10,11 +15,19 @@ pub enum StatsRecord {
         model: Model,
         valid_time: NaiveDateTime,
 
-        hdw: Option<i32>,
-        blow_up_dt: Option<f64>,
-        pft: Option<i32>,
+        /// The elements in this record and their values. Only these columns are written to the
+        /// database, any others already there are left alone.
+        values: Vec<(ClimoElement, Option<f64>)>,
+    },
+    /// Element values from a forecast, stored by initialization time and lead time (hours).
+    FcstData {
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        lead_time: u32,
 
-        dcape: Option<i32>,
+        /// The elements in this record and their values, written like those in `CliData`.
+        values: Vec<(ClimoElement, Option<f64>)>,
     },
     Location {
         site: SiteInfo,
@@ -23,42 +36,69 @@ pub enum StatsRecord {
         lon: f64,
         elev_m: f64,
     },
+    /// The outcome of processing a file from the archive. The hash is `None` if it could not be
+    /// read, and the reason is why it is not `FileStatus::Ok`. The elements (by column name) and
+    /// lead times are what was stored from it, see `ProcessedFile`.
+    File {
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        status: FileStatus,
+        hash: Option<String>,
+        reason: Option<String>,
+        /// The columns of the elements stored from the file.
+        elements: Vec<String>,
+        lead_times: Vec<u32>,
+    },
 }
 
 impl StatsRecord {
+    /// Calculate the elements in `registry` for a sounding and the bufkit surface data that came
+    /// with it.
     pub fn create_cli_data(
         site: SiteInfo,
         model: Model,
         init_time: NaiveDateTime,
         snd: &Sounding,
+        bufkit_anal: &HashMap<&'static str, f64>,
+        registry: &ClimoElementRegistry,
     ) -> Self {
-        let hdw = sounding_analysis::hot_dry_windy(snd)
-            .ok()
-            .map(|hdw| hdw as i32);
-
-        let bua = sounding_analysis::experimental::fire::blow_up(snd, None);
-
-        let blow_up_dt: Option<f64> = match bua {
-            Err(_) => None,
-            Ok(BlowUpAnalysis { delta_t_el, .. }) => Some(delta_t_el.unpack()),
-        };
-
-        let pft: Option<i32> = sounding_analysis::pft(snd, 15.0)
-            .map(|pft| pft.unpack() as i32)
-            .ok();
-
-        let dcape = sounding_analysis::dcape(snd)
-            .ok()
-            .map(|anal| anal.1.unpack() as i32);
-
         StatsRecord::CliData {
             site,
             model,
             valid_time: init_time,
-            hdw,
-            blow_up_dt,
-            pft,
-            dcape,
+            values: calculate_all(snd, bufkit_anal, registry),
+        }
+    }
+
+    /// Calculate the elements in `registry` for a forecast sounding `lead_time` hours after
+    /// `init_time`.
+    pub fn create_fcst_data(
+        site: SiteInfo,
+        model: Model,
+        init_time: NaiveDateTime,
+        lead_time: u32,
+        snd: &Sounding,
+        bufkit_anal: &HashMap<&'static str, f64>,
+        registry: &ClimoElementRegistry,
+    ) -> Self {
+        StatsRecord::FcstData {
+            site,
+            model,
+            init_time,
+            lead_time,
+            values: calculate_all(snd, bufkit_anal, registry),
+        }
+    }
+
+    /// Get the value of an element from a `CliData` or `FcstData` record.
+    pub fn value(&self, element: ClimoElement) -> Option<f64> {
+        match self {
+            StatsRecord::CliData { values, .. } | StatsRecord::FcstData { values, .. } => values
+                .iter()
+                .find(|(elem, _)| *elem == element)
+                .and_then(|(_, val)| *val),
+            StatsRecord::Location { .. } | StatsRecord::File { .. } => None,
         }
     }
 
@@ -89,3 +129,730 @@ impl StatsRecord {
         }
     }
 }
+
+/// Calculates elements for a sounding, sharing the analyses that produce more than one element
+/// so they are only done once, and only if they are needed.
+struct Analysis<'a> {
+    snd: &'a Sounding,
+    bufkit_anal: &'a HashMap<&'static str, f64>,
+    pft_moisture_ratio: f64,
+    blow_up: Option<Option<BlowUpAnalysis>>,
+    pft: Option<Option<PFTAnalysis>>,
+    sb_parcel: Option<Option<ParcelAscentAnalysis>>,
+    ml_parcel: Option<Option<ParcelAscentAnalysis>>,
+    mu_parcel: Option<Option<ParcelAscentAnalysis>>,
+    mixing_height: Option<Option<f64>>,
+    transport_wind: Option<Option<WindSpdDir<MetersPSec>>>,
+    warm_layer: Option<Option<(f64, Option<f64>)>>,
+    storm_motion: Option<Option<WindUV<MetersPSec>>>,
+    effective_layer: Option<Option<Layer>>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(
+        snd: &'a Sounding,
+        bufkit_anal: &'a HashMap<&'static str, f64>,
+        pft_moisture_ratio: f64,
+    ) -> Self {
+        Analysis {
+            snd,
+            bufkit_anal,
+            pft_moisture_ratio,
+            blow_up: None,
+            pft: None,
+            sb_parcel: None,
+            ml_parcel: None,
+            mu_parcel: None,
+            mixing_height: None,
+            transport_wind: None,
+            warm_layer: None,
+            storm_motion: None,
+            effective_layer: None,
+        }
+    }
+
+    fn calculate(&mut self, element: ClimoElement) -> Option<f64> {
+        use ClimoElement::*;
+
+        let snd = self.snd;
+
+        match element {
+            HDW => sounding_analysis::hot_dry_windy(snd).ok().map(f64::trunc),
+            BlowUpDt => self.blow_up().map(|bua| bua.delta_t_el.unpack()),
+            BlowUpHeight => self.blow_up().map(|bua| bua.delta_z_el.unpack()),
+            BlowUpCloudDt => self.blow_up().map(|bua| bua.delta_t_cloud.unpack()),
+            BlowUpMIB => self.blow_up().map(|bua| bua.mib.unpack()),
+            BlowUpPctWet => self.blow_up().map(|bua| bua.pct_wet),
+            PFT => self.pft().map(|anal| anal.pft.unpack().trunc()),
+            DCAPE => sounding_analysis::dcape(snd)
+                .ok()
+                .map(|anal| anal.1.unpack().trunc()),
+            SBCAPE => self.sb_parcel().map(cape),
+            SBCIN => self.sb_parcel().map(cin),
+            MLCAPE => self.ml_parcel().map(cape),
+            MLCIN => self.ml_parcel().map(cin),
+            MUCAPE => self.mu_parcel().map(cape),
+            LCLHeight => self
+                .ml_parcel()
+                .and_then(|anal| anal.lcl_height_agl().into_option())
+                .map(|hgt| hgt.unpack().trunc()),
+            LFCHeight => self
+                .ml_parcel()
+                .filter(|anal| cape(anal) > 0.0)
+                .and_then(|anal| anal.lfc_pressure().into_option())
+                .and_then(|p| height_agl(snd, p)),
+            ELHeight => self
+                .ml_parcel()
+                .filter(|anal| cape(anal) > 0.0)
+                .and_then(|anal| anal.el_pressure().into_option())
+                .and_then(|p| height_agl(snd, p)),
+            PW => sounding_analysis::precipitable_water(snd)
+                .ok()
+                .map(|pw| pw.unpack()),
+            MixingRatio => sounding_analysis::mixed_layer_parcel(snd)
+                .and_then(|pcl| pcl.mixing_ratio())
+                .ok()
+                .map(|mw| mw * 1000.0),
+            HainesLow => sounding_analysis::haines_low(snd).ok().map(f64::from),
+            HainesMid => sounding_analysis::haines_mid(snd).ok().map(f64::from),
+            HainesHigh => sounding_analysis::haines_high(snd).ok().map(f64::from),
+            LapseRate850To500 => lapse_rate(snd, HectoPascal(850.0), HectoPascal(500.0)),
+            LapseRate700To500 => lapse_rate(snd, HectoPascal(700.0), HectoPascal(500.0)),
+            MixingHeight => self.mixing_height().map(f64::trunc),
+            TransportWindSpeed => self.transport_wind().map(|wind| wind.speed.unpack()),
+            TransportWindDirection => self.transport_wind().map(|wind| wind.direction.trunc()),
+            VentilationIndex => match self.mixing_height() {
+                // No mixed layer means no transport wind, but there is no ventilation either.
+                Some(hgt) if hgt < 1.0 => Some(0.0),
+                Some(hgt) => self
+                    .transport_wind()
+                    .map(|wind| (hgt * wind.speed.unpack()).trunc()),
+                None => None,
+            },
+            FreezingLevel => zero_level(snd, sounding_analysis::freezing_levels(snd), |row| {
+                row.temperature.into_option()
+            }),
+            WetBulbZero => zero_level(snd, sounding_analysis::wet_bulb_zero_levels(snd), |row| {
+                row.wet_bulb.into_option()
+            }),
+            WarmLayerDepth => self.warm_layer().map(|(depth, _)| depth.trunc()),
+            WarmLayerMaxT => self.warm_layer().and_then(|(_, max_t)| max_t),
+            BulkShear0To1km => bulk_shear(snd, Meters(1000.0)),
+            BulkShear0To6km => bulk_shear(snd, Meters(6000.0)),
+            SRH0To1km => self.srh(Meters(1000.0)),
+            SRH0To3km => self.srh(Meters(3000.0)),
+            EffectiveSRH => self.effective_srh(),
+            EffectiveBulkShear => self.effective_shear(),
+            STP => {
+                let ml_cape = self.ml_parcel().map(cape)?;
+                let ml_cin = self.ml_parcel().map(cin)?;
+                if ml_cape <= 0.0 {
+                    return Some(0.0);
+                }
+                let lcl = self.calculate(LCLHeight)?;
+                let srh = self.effective_srh()?;
+                let shear = self.effective_shear()?;
+
+                Some(stp(ml_cape, ml_cin, lcl, srh, shear))
+            }
+            SCP => {
+                let mu_cape = self.mu_parcel().map(cape)?;
+                let srh = self.effective_srh()?;
+                let shear = self.effective_shear()?;
+
+                Some(scp(mu_cape, srh, shear))
+            }
+            Level { element, pressure } => level_value(snd, element, pressure),
+            SfcTemperature => snd.sfc_temperature().into_option().map(|t| t.unpack()),
+            SfcDewPoint => snd.sfc_dew_point().into_option().map(|td| td.unpack()),
+            SfcRH => snd
+                .sfc_temperature()
+                .into_option()
+                .and_then(|t| metfor::rh(t, snd.sfc_dew_point().into_option()?))
+                .map(|rh| rh * 100.0),
+            SfcWindSpeed => snd
+                .sfc_wind()
+                .into_option()
+                .map(|wind| MetersPSec::from(wind.speed).unpack()),
+            SfcWindDirection => snd
+                .sfc_wind()
+                .into_option()
+                .map(|wind| wind.direction.trunc()),
+            MSLP => snd.mslp().into_option().map(|p| p.unpack()),
+            Precip1Hr => self.bufkit_value("Precipitation1HrMm"),
+            ConvectivePrecip1Hr => self.bufkit_value("ConvectivePrecip1HrMm"),
+            SnowFall1Hr => self.bufkit_value("SnowFall1HourKgPerMeterSquared"),
+            SnowRatio => self.bufkit_value("SnowRatio"),
+            SkinTemperature => self.bufkit_value("SkinTemperature"),
+            Visibility => self.bufkit_value("VisibilityKm"),
+            FixedWindPFT { mean_wind_mps, .. } => {
+                let anal = self.pft()?;
+
+                pft_with_wind(snd, anal, MetersPSec(f64::from(mean_wind_mps))).map(f64::trunc)
+            }
+            Custom(calc) => calc.compute(snd),
+        }
+    }
+
+    /// A value bufkit provides directly.
+    fn bufkit_value(&self, key: &str) -> Option<f64> {
+        self.bufkit_anal.get(key).copied()
+    }
+
+    fn blow_up(&mut self) -> Option<&BlowUpAnalysis> {
+        let snd = self.snd;
+
+        self.blow_up
+            .get_or_insert_with(|| sounding_analysis::experimental::fire::blow_up(snd, None).ok())
+            .as_ref()
+    }
+
+    fn pft(&mut self) -> Option<&PFTAnalysis> {
+        let snd = self.snd;
+        let moisture_ratio = self.pft_moisture_ratio;
+
+        self.pft
+            .get_or_insert_with(|| sounding_analysis::pft_analysis(snd, moisture_ratio).ok())
+            .as_ref()
+    }
+
+    fn sb_parcel(&mut self) -> Option<&ParcelAscentAnalysis> {
+        let snd = self.snd;
+
+        self.sb_parcel
+            .get_or_insert_with(|| {
+                sounding_analysis::surface_parcel(snd)
+                    .and_then(|pcl| sounding_analysis::lift_parcel(pcl, snd))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    fn ml_parcel(&mut self) -> Option<&ParcelAscentAnalysis> {
+        let snd = self.snd;
+
+        self.ml_parcel
+            .get_or_insert_with(|| {
+                sounding_analysis::mixed_layer_parcel(snd)
+                    .and_then(|pcl| sounding_analysis::lift_parcel(pcl, snd))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    fn mu_parcel(&mut self) -> Option<&ParcelAscentAnalysis> {
+        let snd = self.snd;
+
+        self.mu_parcel
+            .get_or_insert_with(|| {
+                sounding_analysis::most_unstable_parcel(snd)
+                    .and_then(|pcl| sounding_analysis::lift_parcel(pcl, snd))
+                    .ok()
+            })
+            .as_ref()
+    }
+
+    /// The mixing height in meters AGL.
+    fn mixing_height(&mut self) -> Option<f64> {
+        let snd = self.snd;
+
+        *self.mixing_height.get_or_insert_with(|| mixing_height(snd))
+    }
+
+    /// The pressure weighted mean wind from the surface to the mixing height.
+    fn transport_wind(&mut self) -> Option<WindSpdDir<MetersPSec>> {
+        let snd = self.snd;
+        let mixing_height = self.mixing_height();
+
+        *self.transport_wind.get_or_insert_with(|| {
+            let layer = sounding_analysis::layer_agl(snd, Meters(mixing_height?)).ok()?;
+            sounding_analysis::mean_wind(&layer, snd)
+                .ok()
+                .map(WindSpdDir::from)
+        })
+    }
+
+    /// The depth and maximum temperature of the deepest above freezing layer aloft. The depth is
+    /// zero if there is no such layer.
+    fn warm_layer(&mut self) -> Option<(f64, Option<f64>)> {
+        let snd = self.snd;
+
+        *self.warm_layer.get_or_insert_with(|| {
+            let layers = sounding_analysis::warm_temperature_layer_aloft(snd).ok()?;
+            if layers.is_empty() {
+                return Some((0.0, None));
+            }
+
+            layers
+                .iter()
+                .filter_map(|lyr| {
+                    let depth = lyr.height_thickness()?.unpack();
+                    let max_t = sounding_analysis::max_temperature_in_layer(snd, lyr)
+                        .ok()
+                        .and_then(|lvl| lvl.temperature.into_option())
+                        .map(|t| t.unpack());
+
+                    Some((depth, max_t))
+                })
+                .max_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap())
+        })
+    }
+
+    /// The Bunkers right mover storm motion.
+    fn storm_motion(&mut self) -> Option<WindUV<MetersPSec>> {
+        let snd = self.snd;
+
+        *self.storm_motion.get_or_insert_with(|| {
+            sounding_analysis::bunkers_storm_motion(snd)
+                .ok()
+                .map(|(right, _left)| right)
+        })
+    }
+
+    /// Storm relative helicity from the surface to `top` AGL for the right mover.
+    fn srh(&mut self, top: Meters) -> Option<f64> {
+        let snd = self.snd;
+        let storm_motion = self.storm_motion()?;
+
+        let layer = sounding_analysis::layer_agl(snd, top).ok()?;
+        sounding_analysis::sr_helicity(&layer, storm_motion, snd)
+            .ok()
+            .map(|srh| srh.unpack().trunc())
+    }
+
+    /// The effective inflow layer.
+    fn effective_layer(&mut self) -> Option<&Layer> {
+        let snd = self.snd;
+
+        self.effective_layer
+            .get_or_insert_with(|| sounding_analysis::effective_inflow_layer(snd))
+            .as_ref()
+    }
+
+    /// Storm relative helicity in the effective inflow layer for the right mover.
+    fn effective_srh(&mut self) -> Option<f64> {
+        let snd = self.snd;
+        let storm_motion = self.storm_motion()?;
+
+        match self.effective_layer() {
+            Some(layer) => sounding_analysis::sr_helicity(layer, storm_motion, snd)
+                .ok()
+                .map(|srh| srh.unpack().trunc()),
+            None => Some(0.0),
+        }
+    }
+
+    /// The bulk wind shear from the bottom of the effective inflow layer to half the height of the
+    /// most unstable parcel equilibrium level.
+    fn effective_shear(&mut self) -> Option<f64> {
+        let snd = self.snd;
+
+        let el_pressure = self
+            .mu_parcel()
+            .and_then(|anal| anal.el_pressure().into_option());
+
+        let bottom = match self.effective_layer() {
+            Some(layer) => layer.bottom,
+            None => return Some(0.0),
+        };
+
+        let bottom_hgt = bottom.height.into_option()?;
+        let el_hgt = sounding_analysis::linear_interpolate(
+            snd.pressure_profile(),
+            snd.height_profile(),
+            el_pressure?,
+        )
+        .into_option()?;
+
+        let top_hgt = bottom_hgt + (el_hgt - bottom_hgt) * 0.5;
+        let top_pressure = sounding_analysis::linear_interpolate(
+            snd.height_profile(),
+            snd.pressure_profile(),
+            top_hgt,
+        )
+        .into_option()?;
+        let top = sounding_analysis::linear_interpolate_sounding(snd, top_pressure).ok()?;
+
+        let shear = Layer { bottom, top }.wind_shear()?;
+
+        Some(shear.u.unpack().hypot(shear.v.unpack()))
+    }
+}
+
+/// Get the CAPE of a parcel ascent, zero if the parcel was never positively buoyant.
+fn cape(anal: &ParcelAscentAnalysis) -> f64 {
+    anal.cape()
+        .into_option()
+        .map(|cape| cape.unpack().trunc())
+        .unwrap_or(0.0)
+}
+
+/// Get the CIN of a parcel ascent, zero if the parcel was never negatively buoyant.
+fn cin(anal: &ParcelAscentAnalysis) -> f64 {
+    anal.cin()
+        .into_option()
+        .map(|cin| cin.unpack().trunc())
+        .unwrap_or(0.0)
+}
+
+/// Get the temperature lapse rate between two pressure levels, positive when the temperature
+/// decreases with height.
+fn lapse_rate(snd: &Sounding, bottom: HectoPascal, top: HectoPascal) -> Option<f64> {
+    sounding_analysis::pressure_layer(snd, bottom, top)
+        .ok()
+        .and_then(|layer| layer.lapse_rate())
+        .map(|lr| -lr.unpack())
+}
+
+/// The effective layer significant tornado parameter from the mixed layer CAPE, CIN and LCL
+/// height (m AGL), the effective storm relative helicity and the effective bulk shear (m/s).
+fn stp(ml_cape: f64, ml_cin: f64, lcl: f64, srh: f64, shear: f64) -> f64 {
+    let lcl_term = ((2000.0 - lcl) / 1000.0).clamp(0.0, 1.0);
+    let shear_term = if shear < 12.5 {
+        0.0
+    } else {
+        shear.min(30.0) / 20.0
+    };
+    let cin_term = ((200.0 + ml_cin) / 150.0).clamp(0.0, 1.0);
+
+    ml_cape / 1500.0 * lcl_term * srh / 150.0 * shear_term * cin_term
+}
+
+/// The supercell composite parameter from the most unstable parcel, the effective storm relative
+/// helicity and the effective bulk shear (m/s).
+fn scp(mu_cape: f64, srh: f64, shear: f64) -> f64 {
+    let shear_term = if shear < 10.0 {
+        0.0
+    } else {
+        shear.min(20.0) / 20.0
+    };
+
+    mu_cape / 1000.0 * srh / 50.0 * shear_term
+}
+
+/// Get the mixing height (m AGL), where a dry adiabat from the surface temperature reaches the
+/// environmental temperature profile.
+fn mixing_height(snd: &Sounding) -> Option<f64> {
+    let elevation = snd.station_info().elevation().into_option()?.unpack();
+    let sfc_theta = metfor::potential_temperature(
+        snd.station_pressure().into_option()?,
+        snd.sfc_temperature().into_option()?,
+    )
+    .unpack();
+
+    let mut below: Option<(f64, f64)> = None;
+    for row in snd.bottom_up() {
+        let (pressure, temperature, height) = match (
+            row.pressure.into_option(),
+            row.temperature.into_option(),
+            row.height.into_option(),
+        ) {
+            (Some(p), Some(t), Some(h)) => (p, t, h.unpack()),
+            _ => continue,
+        };
+
+        let theta = metfor::potential_temperature(pressure, temperature).unpack();
+        if theta > sfc_theta {
+            let hgt = match below {
+                Some((theta_below, hgt_below)) => {
+                    hgt_below
+                        + (sfc_theta - theta_below) / (theta - theta_below) * (height - hgt_below)
+                }
+                None => height,
+            };
+
+            return Some((hgt - elevation).max(0.0));
+        }
+
+        below = Some((theta, height));
+    }
+
+    None
+}
+
+/// Calculate the values of the elements in `registry` for a sounding.
+fn calculate_all(
+    snd: &Sounding,
+    bufkit_anal: &HashMap<&'static str, f64>,
+    registry: &ClimoElementRegistry,
+) -> Vec<(ClimoElement, Option<f64>)> {
+    let mut anal = Analysis::new(snd, bufkit_anal, registry.pft_moisture_ratio());
+
+    registry
+        .elements()
+        .iter()
+        .map(|&element| (element, anal.calculate(element)))
+        .collect()
+}
+
+/// Get the height (m MSL) of the highest of `levels` where a profile crosses 0C. If there are no
+/// crossings and the lowest value of the profile is below freezing, this is the surface.
+fn zero_level<F>(snd: &Sounding, levels: sounding_analysis::Result<Levels>, value: F) -> Option<f64>
+where
+    F: Fn(&DataRow) -> Option<Celsius>,
+{
+    let highest = levels
+        .ok()?
+        .iter()
+        .filter_map(|lvl| lvl.height.into_option())
+        .map(|hgt| hgt.unpack())
+        .fold(None, |max: Option<f64>, hgt| {
+            Some(max.map_or(hgt, |max| max.max(hgt)))
+        });
+
+    match highest {
+        Some(hgt) => Some(hgt.trunc()),
+        None => {
+            let lowest = snd.bottom_up().find_map(|row| value(&row))?;
+            if lowest <= Celsius(0.0) {
+                snd.station_info()
+                    .elevation()
+                    .into_option()
+                    .map(|elev| elev.unpack().trunc())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Calculate the PFT of a plume with another mean wind speed in the mixed layer, using the same
+/// formula as `sounding_analysis::pft_analysis`.
+fn pft_with_wind(snd: &Sounding, anal: &PFTAnalysis, mean_wind: MetersPSec) -> Option<f64> {
+    // The analysis starts from the lowest level with a pressure.
+    let p_sfc = snd
+        .pressure_profile()
+        .iter()
+        .find_map(|p| p.into_option())?;
+    let theta_fc = anal.theta_ml + anal.d_theta;
+
+    let pft = metfor::pft(
+        anal.z_fc,
+        anal.p_fc,
+        mean_wind,
+        anal.d_theta,
+        theta_fc,
+        p_sfc,
+    );
+
+    Some(pft.unpack())
+}
+
+/// Get the magnitude of the bulk wind shear from the surface to `top` AGL in m/s.
+fn bulk_shear(snd: &Sounding, top: Meters) -> Option<f64> {
+    let shear = sounding_analysis::layer_agl(snd, top).ok()?.wind_shear()?;
+
+    Some(shear.u.unpack().hypot(shear.v.unpack()))
+}
+
+/// Get the value of a state variable at a mandatory pressure level.
+fn level_value(snd: &Sounding, element: LevelElement, pressure: MandatoryLevel) -> Option<f64> {
+    let row =
+        sounding_analysis::linear_interpolate_sounding(snd, HectoPascal(pressure.hpa())).ok()?;
+
+    match element {
+        LevelElement::Temperature => row.temperature.into_option().map(|t| t.unpack()),
+        LevelElement::DewPoint => row.dew_point.into_option().map(|td| td.unpack()),
+        LevelElement::WindSpeed => row
+            .wind
+            .into_option()
+            .map(|wind| MetersPSec::from(wind.speed).unpack()),
+        LevelElement::WindDirection => row.wind.into_option().map(|wind| wind.direction.trunc()),
+        LevelElement::Height => row.height.into_option().map(|hgt| hgt.unpack().trunc()),
+    }
+}
+
+/// Get the height above ground level of a pressure level, truncated to whole meters.
+fn height_agl(snd: &Sounding, pressure: HectoPascal) -> Option<f64> {
+    let elevation = snd.station_info().elevation().into_option()?;
+
+    sounding_analysis::linear_interpolate_sounding(snd, pressure)
+        .ok()
+        .and_then(|row| row.height.into_option())
+        .map(|hgt| (hgt - elevation).unpack().trunc())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metfor::{CelsiusDiff, Knots};
+    use sounding_bufkit::BufkitData;
+
+    /// The soundings in the test file with their bufkit analyses.
+    fn soundings() -> Vec<(Sounding, HashMap<&'static str, f64>)> {
+        let data = include_str!("../../test_data/2017040106Z_gfs_kmso.buf");
+
+        BufkitData::init(data, "").unwrap().into_iter().collect()
+    }
+
+    /// The same sounding with no wind at any level.
+    fn calm(snd: Sounding) -> Sounding {
+        let calm = WindSpdDir {
+            speed: Knots(0.0),
+            direction: 0.0,
+        };
+        let winds = snd.wind_profile()[1..]
+            .iter()
+            .map(|wind| wind.map_t(|_| calm))
+            .collect();
+
+        snd.with_sfc_wind(calm).with_wind_profile(winds)
+    }
+
+    const PFT_10MPS: ClimoElement = ClimoElement::FixedWindPFT {
+        name: "pft_10mps",
+        mean_wind_mps: 10,
+    };
+
+    #[test]
+    fn test_pft_with_wind() {
+        for (snd, _) in soundings() {
+            let anal = sounding_analysis::pft_analysis(&snd, 15.0).unwrap();
+            let pft = pft_with_wind(&snd, &anal, anal.u_ml).unwrap();
+            assert!((pft - anal.pft.unpack()).abs() < 1.0e-6);
+        }
+    }
+
+    #[test]
+    fn test_fixed_wind_pft_in_calm_sounding() {
+        let (snd, bufkit_anal) = soundings().remove(0);
+        let snd = calm(snd);
+
+        let mut anal = Analysis::new(&snd, &bufkit_anal, 15.0);
+        assert_eq!(anal.calculate(ClimoElement::PFT), Some(0.0));
+
+        let pft_10 = anal.calculate(PFT_10MPS).unwrap();
+        assert!(pft_10 > 0.0);
+    }
+
+    #[test]
+    fn test_stp() {
+        assert_eq!(stp(1500.0, 0.0, 1000.0, 150.0, 20.0), 1.0);
+
+        // The LCL term is clamped between zero and one.
+        assert_eq!(stp(1500.0, 0.0, 500.0, 150.0, 20.0), 1.0);
+        assert_eq!(stp(1500.0, 0.0, 2500.0, 150.0, 20.0), 0.0);
+        assert_eq!(stp(1500.0, 0.0, 1500.0, 150.0, 20.0), 0.5);
+
+        // No shear term below 12.5 m/s and it is capped at 30 m/s.
+        assert_eq!(stp(1500.0, 0.0, 1000.0, 150.0, 12.0), 0.0);
+        assert_eq!(stp(1500.0, 0.0, 1000.0, 150.0, 40.0), 1.5);
+
+        // The CIN term is clamped between zero and one.
+        assert_eq!(stp(1500.0, -125.0, 1000.0, 150.0, 20.0), 0.5);
+        assert_eq!(stp(1500.0, -250.0, 1000.0, 150.0, 20.0), 0.0);
+    }
+
+    #[test]
+    fn test_scp() {
+        assert_eq!(scp(1000.0, 50.0, 20.0), 1.0);
+
+        // No shear term below 10 m/s and it is capped at 20 m/s.
+        assert_eq!(scp(1000.0, 50.0, 9.0), 0.0);
+        assert_eq!(scp(1000.0, 50.0, 10.0), 0.5);
+        assert_eq!(scp(1000.0, 50.0, 30.0), 1.0);
+    }
+
+    #[test]
+    fn test_stp_and_scp_in_soundings() {
+        for (snd, bufkit_anal) in soundings() {
+            let mut anal = Analysis::new(&snd, &bufkit_anal, 15.0);
+
+            let ml_cape = anal.ml_parcel().map(cape).unwrap();
+            let stp = anal.calculate(ClimoElement::STP);
+            if ml_cape <= 0.0 {
+                assert_eq!(stp, Some(0.0));
+            }
+            if let Some(stp) = stp {
+                assert!(stp >= 0.0);
+            }
+            if let Some(scp) = anal.calculate(ClimoElement::SCP) {
+                assert!(scp >= 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mixing_height() {
+        let heights: Vec<f64> = soundings()
+            .iter()
+            .map(|(snd, _)| mixing_height(snd).unwrap())
+            .collect();
+
+        // Under the overnight inversion the surface air is not mixed at all.
+        assert!(heights[..4].iter().all(|&hgt| hgt == 0.0));
+
+        // The mixed layer deepens through the afternoon, then collapses in the evening.
+        assert!(heights[4] > 0.0);
+        assert!(heights[5] > heights[4]);
+        assert!(heights[6] < heights[5]);
+    }
+
+    #[test]
+    fn test_mixing_height_with_warmer_surface() {
+        let (snd, _) = soundings().remove(5);
+        let sfc_t = snd.sfc_temperature().unwrap();
+        let warmer = snd.clone().with_sfc_temperature(sfc_t + CelsiusDiff(5.0));
+
+        assert!(mixing_height(&warmer).unwrap() > mixing_height(&snd).unwrap());
+    }
+
+    /// A level at `height` m MSL.
+    fn level(height: f64) -> DataRow {
+        DataRow {
+            height: Meters(height).into(),
+            ..DataRow::default()
+        }
+    }
+
+    #[test]
+    fn test_zero_level() {
+        let (snd, _) = soundings().remove(0);
+        let below_freezing = |_: &DataRow| Some(Celsius(-5.0));
+        let above_freezing = |_: &DataRow| Some(Celsius(5.0));
+
+        // The highest crossing wins, whatever the temperature at the surface.
+        let levels = Ok(vec![level(2500.6), level(1500.0), level(3100.9)]);
+        assert_eq!(zero_level(&snd, levels, above_freezing), Some(3100.0));
+
+        // With no crossings it is at the surface only if the surface is below freezing.
+        assert_eq!(zero_level(&snd, Ok(vec![]), below_freezing), Some(972.0));
+        assert_eq!(zero_level(&snd, Ok(vec![]), above_freezing), None);
+
+        let levels = Err(sounding_analysis::AnalysisError::MissingProfile);
+        assert_eq!(zero_level(&snd, levels, below_freezing), None);
+    }
+
+    #[test]
+    fn test_freezing_levels_in_soundings() {
+        for (snd, bufkit_anal) in soundings() {
+            let mut anal = Analysis::new(&snd, &bufkit_anal, 15.0);
+
+            let freezing = anal.calculate(ClimoElement::FreezingLevel).unwrap();
+            let wet_bulb_zero = anal.calculate(ClimoElement::WetBulbZero).unwrap();
+            assert!(freezing >= 972.0);
+            assert!(wet_bulb_zero >= 972.0);
+            assert!(wet_bulb_zero <= freezing);
+        }
+    }
+
+    #[test]
+    fn test_warm_layer() {
+        let mut soundings = soundings();
+
+        // Only the 12Z sounding has an above freezing layer over a below freezing surface.
+        let (snd, bufkit_anal) = soundings.remove(2);
+        let mut anal = Analysis::new(&snd, &bufkit_anal, 15.0);
+        let depth = anal.calculate(ClimoElement::WarmLayerDepth).unwrap();
+        let max_t = anal.calculate(ClimoElement::WarmLayerMaxT).unwrap();
+        assert!(depth > 0.0);
+        assert_eq!(depth, depth.trunc());
+        assert!(max_t > 0.0);
+
+        for (snd, bufkit_anal) in soundings {
+            let mut anal = Analysis::new(&snd, &bufkit_anal, 15.0);
+            assert_eq!(anal.calculate(ClimoElement::WarmLayerDepth), Some(0.0));
+            assert_eq!(anal.calculate(ClimoElement::WarmLayerMaxT), None);
+        }
+    }
+}
@@ -9,12 +9,22 @@
 // Public API
 //
 pub use crate::{
-    climo_db::{ClimoDB, ClimoElement, ClimoPopulateInterface, StatsRecord},
+    builder::{BuildEvent, ClimoBuilder},
+    climo_db::{
+        content_hash, BadFiles, ClimoDB, ClimoElement, ClimoElementCalc, ClimoElementRegistry,
+        ClimoPopulateInterface, ClimoQueryFilter, ClimoQueryInterface, FileStatus, ForecastPairs,
+        LevelElement, MandatoryLevel, ModelPairs, ProcessedFile, StatsRecord,
+    },
     error::BufcliError,
+    stats::{
+        correlation, direction_difference, mean_direction, percentile_rank, ErrorSummary, Summary,
+    },
 };
 
 //
 // Private implementation.
 //
+mod builder;
 mod climo_db;
 mod error;
+mod stats;
@@ -0,0 +1,276 @@
+//! Statistics calculated from climo data.
+
+/// Calculate the percentile rank (0-100) of `value` within `climo`.
+///
+/// Values equal to `value` count as half below and half above it. Returns `None` if `climo` is
+/// empty.
+pub fn percentile_rank(value: f64, climo: &[f64]) -> Option<f64> {
+    if climo.is_empty() {
+        return None;
+    }
+
+    let (below, equal) = climo.iter().fold((0usize, 0usize), |(below, equal), &v| {
+        if v < value {
+            (below + 1, equal)
+        } else if v == value {
+            (below, equal + 1)
+        } else {
+            (below, equal)
+        }
+    });
+
+    Some((below as f64 + 0.5 * equal as f64) / climo.len() as f64 * 100.0)
+}
+
+/// Calculate the Pearson correlation coefficient of paired values.
+///
+/// Returns `None` if there are fewer than two pairs or either set of values is constant.
+pub fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    if pairs.len() < 2 {
+        return None;
+    }
+
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|(_, y)| y).sum::<f64>() / n;
+
+    let (cov, var_x, var_y) = pairs
+        .iter()
+        .fold((0.0, 0.0, 0.0), |(cov, var_x, var_y), (x, y)| {
+            let (dx, dy) = (x - mean_x, y - mean_y);
+            (cov + dx * dy, var_x + dx * dx, var_y + dy * dy)
+        });
+
+    if var_x <= 0.0 || var_y <= 0.0 {
+        return None;
+    }
+
+    Some(cov / (var_x * var_y).sqrt())
+}
+
+/// The difference between two directions in degrees, from -180 to 180, positive if `a` is
+/// clockwise of `b`.
+pub fn direction_difference(a: f64, b: f64) -> f64 {
+    let diff = (a - b).rem_euclid(360.0);
+    if diff > 180.0 {
+        diff - 360.0
+    } else {
+        diff
+    }
+}
+
+/// Calculate the mean of directions in degrees, from 0 to 360.
+///
+/// Returns `None` if there are no directions or they cancel out, e.g. north and south.
+pub fn mean_direction(dirs: &[f64]) -> Option<f64> {
+    let (sin, cos) = dirs.iter().fold((0.0, 0.0), |(sin, cos), dir: &f64| {
+        let dir = dir.to_radians();
+        (sin + dir.sin(), cos + dir.cos())
+    });
+
+    if sin.hypot(cos) <= 1.0e-9 * dirs.len() as f64 {
+        return None;
+    }
+
+    Some(sin.atan2(cos).to_degrees().rem_euclid(360.0))
+}
+
+/// Summary statistics for a collection of values.
+#[derive(Clone, Debug)]
+pub struct Summary {
+    sorted: Vec<f64>,
+    mean: f64,
+}
+
+impl Summary {
+    /// Summarize a collection of values. Returns `None` if there are no values, or any of them are
+    /// NaN.
+    pub fn new(mut vals: Vec<f64>) -> Option<Self> {
+        if vals.is_empty() || vals.iter().any(|v| v.is_nan()) {
+            return None;
+        }
+
+        vals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let mean = vals.iter().sum::<f64>() / vals.len() as f64;
+
+        Some(Summary { sorted: vals, mean })
+    }
+
+    /// The number of values.
+    pub fn count(&self) -> usize {
+        self.sorted.len()
+    }
+
+    /// The smallest value.
+    pub fn min(&self) -> f64 {
+        self.sorted[0]
+    }
+
+    /// The largest value.
+    pub fn max(&self) -> f64 {
+        self.sorted[self.sorted.len() - 1]
+    }
+
+    /// The mean of the values.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// The value at percentile `pct` (0-100), linearly interpolating between values.
+    pub fn percentile(&self, pct: f64) -> f64 {
+        let pos = (pct.clamp(0.0, 100.0) / 100.0) * (self.sorted.len() - 1) as f64;
+        let below = pos.floor() as usize;
+        let above = pos.ceil() as usize;
+
+        let frac = pos - below as f64;
+        self.sorted[below] + frac * (self.sorted[above] - self.sorted[below])
+    }
+}
+
+/// Errors of forecasts compared to the values they verify against.
+#[derive(Clone, Copy, Debug)]
+pub struct ErrorSummary {
+    count: usize,
+    bias: f64,
+    mae: f64,
+    rmse: f64,
+}
+
+impl ErrorSummary {
+    /// Summarize the errors of `(forecast, observed)` pairs. Returns `None` if there are no pairs,
+    /// or any of the values are NaN.
+    pub fn new(pairs: &[(f64, f64)]) -> Option<Self> {
+        Self::from_errors(pairs, |f, o| f - o)
+    }
+
+    /// Summarize the errors of `(forecast, observed)` pairs of directions in degrees, using the
+    /// smallest angle between them. Returns `None` if there are no pairs, or any of the values are
+    /// NaN.
+    pub fn new_directions(pairs: &[(f64, f64)]) -> Option<Self> {
+        Self::from_errors(pairs, direction_difference)
+    }
+
+    fn from_errors<F>(pairs: &[(f64, f64)], error: F) -> Option<Self>
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        if pairs.is_empty() || pairs.iter().any(|(f, o)| f.is_nan() || o.is_nan()) {
+            return None;
+        }
+
+        let (sum, sum_abs, sum_sq) = pairs
+            .iter()
+            .map(|&(f, o)| error(f, o))
+            .fold((0.0, 0.0, 0.0), |(sum, sum_abs, sum_sq), err| {
+                (sum + err, sum_abs + err.abs(), sum_sq + err * err)
+            });
+        let n = pairs.len() as f64;
+
+        Some(ErrorSummary {
+            count: pairs.len(),
+            bias: sum / n,
+            mae: sum_abs / n,
+            rmse: (sum_sq / n).sqrt(),
+        })
+    }
+
+    /// The number of pairs.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// The mean error, positive if the forecasts are too high.
+    pub fn bias(&self) -> f64 {
+        self.bias
+    }
+
+    /// The mean absolute error.
+    pub fn mae(&self) -> f64 {
+        self.mae
+    }
+
+    /// The root mean square error.
+    pub fn rmse(&self) -> f64 {
+        self.rmse
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1.0e-9
+    }
+
+    #[test]
+    fn test_percentile_rank() {
+        let climo = [1.0, 2.0, 3.0, 4.0];
+
+        assert_eq!(percentile_rank(0.0, &climo), Some(0.0));
+        assert_eq!(percentile_rank(2.5, &climo), Some(50.0));
+        assert_eq!(percentile_rank(5.0, &climo), Some(100.0));
+        assert_eq!(percentile_rank(1.0, &[]), None);
+    }
+
+    #[test]
+    fn test_percentile_rank_ties() {
+        // Ties count half below and half above.
+        assert_eq!(percentile_rank(2.0, &[1.0, 2.0, 2.0, 3.0]), Some(50.0));
+        assert_eq!(percentile_rank(2.0, &[1.0, 2.0, 3.0, 4.0]), Some(37.5));
+        assert_eq!(percentile_rank(0.0, &[0.0; 10]), Some(50.0));
+    }
+
+    #[test]
+    fn test_summary_percentile() {
+        let summary = Summary::new(vec![40.0, 10.0, 30.0, 20.0]).unwrap();
+
+        assert_eq!(summary.count(), 4);
+        assert_eq!(summary.min(), 10.0);
+        assert_eq!(summary.max(), 40.0);
+        assert_eq!(summary.mean(), 25.0);
+
+        assert!(approx_eq(summary.percentile(0.0), 10.0));
+        assert!(approx_eq(summary.percentile(100.0), 40.0));
+        // Halfway between the 2nd and 3rd values.
+        assert!(approx_eq(summary.percentile(50.0), 25.0));
+        // A third of the way from the 1st to the 2nd value.
+        assert!(approx_eq(summary.percentile(10.0), 13.0));
+        assert!(approx_eq(summary.percentile(90.0), 37.0));
+        // Out of range is clamped.
+        assert!(approx_eq(summary.percentile(150.0), 40.0));
+
+        let one = Summary::new(vec![7.0]).unwrap();
+        assert!(approx_eq(one.percentile(75.0), 7.0));
+
+        assert!(Summary::new(vec![]).is_none());
+        assert!(Summary::new(vec![1.0, f64::NAN]).is_none());
+    }
+
+    #[test]
+    fn test_error_summary() {
+        let errors = ErrorSummary::new(&[(3.0, 1.0), (1.0, 3.0), (5.0, 1.0)]).unwrap();
+
+        assert_eq!(errors.count(), 3);
+        assert!(approx_eq(errors.bias(), 4.0 / 3.0));
+        assert!(approx_eq(errors.mae(), 8.0 / 3.0));
+        assert!(approx_eq(errors.rmse(), 8.0_f64.sqrt()));
+    }
+
+    #[test]
+    fn test_directions() {
+        assert!(approx_eq(direction_difference(10.0, 350.0), 20.0));
+        assert!(approx_eq(direction_difference(350.0, 10.0), -20.0));
+        assert!(approx_eq(direction_difference(90.0, 90.0), 0.0));
+
+        let north = mean_direction(&[350.0, 10.0]).unwrap();
+        assert!(approx_eq(direction_difference(north, 0.0), 0.0));
+        assert!(approx_eq(mean_direction(&[80.0, 100.0]).unwrap(), 90.0));
+        assert!(mean_direction(&[0.0, 180.0]).is_none());
+        assert!(mean_direction(&[]).is_none());
+
+        let errors = ErrorSummary::new_directions(&[(10.0, 350.0), (340.0, 20.0)]).unwrap();
+        assert!(approx_eq(errors.bias(), -10.0));
+        assert!(approx_eq(errors.mae(), 30.0));
+    }
+}